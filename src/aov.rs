@@ -0,0 +1,167 @@
+use crate::color::Color;
+use crate::debug::id_color;
+use crate::material::Lobe;
+use crate::ray::Ray;
+use crate::scene::HitRecord;
+use crate::spectrum::{Wavelengths, xyz_to_rgb};
+use crate::util::{black, gray, weighted};
+use crate::vector::Vector3;
+
+/// Arbitrary output variables of one path: what it saw at its first hit,
+/// and its radiance split by how the light reached the camera. Light that
+/// took a single bounce is direct, and light that took more is indirect.
+/// Both count as diffuse or specular by the lobe the first bounce sampled,
+/// with reflection and transmission off smooth surfaces both specular.
+/// `emission` is light seen without any bounce at all.
+#[derive(Copy, Clone, Debug)]
+pub struct PathAovs {
+    pub albedo: Color,
+    pub normal: Vector3,
+    pub depth: f64,
+    pub emission: Color,
+    pub direct_diffuse: Color,
+    pub indirect_diffuse: Color,
+    pub specular: Color,
+    pub object_id: Color,
+    pub material_id: Color,
+    first_lobe: Option<Lobe>,
+    pending: Color,
+}
+
+impl Default for PathAovs {
+    fn default() -> Self {
+        PathAovs {
+            albedo: black(),
+            normal: Vector3::zero(),
+            depth: 0.0,
+            emission: black(),
+            direct_diffuse: black(),
+            indirect_diffuse: black(),
+            specular: black(),
+            object_id: black(),
+            material_id: black(),
+            first_lobe: None,
+            pending: black(),
+        }
+    }
+}
+
+impl PathAovs {
+    /// Records the surface the camera ray hit.
+    pub(crate) fn first_hit(&mut self, ray: &Ray, rec: &HitRecord) {
+        self.normal = rec.normal;
+        self.depth = rec.t * ray.direction.length();
+        self.object_id = id_color(rec.object as *const _ as *const () as usize);
+        self.material_id =
+            id_color(rec.material as *const _ as *const () as usize);
+    }
+
+    /// Records the first bounce, which decides where the light it carries
+    /// is counted.
+    pub(crate) fn scattered(&mut self, lobe: Lobe, attenuation: &Vector3) {
+        if self.first_lobe.is_some() {
+            return;
+        }
+        self.albedo = Color {
+            r: attenuation.x,
+            g: attenuation.y,
+            b: attenuation.z,
+        };
+        self.first_lobe = Some(lobe);
+        let pending = std::mem::replace(&mut self.pending, black());
+        self.add_light(1, pending);
+    }
+
+    /// Counts `light` that reached the camera after `bounces` bounces.
+    pub(crate) fn add_light(&mut self, bounces: usize, light: Color) {
+        let layer = match (bounces, self.first_lobe) {
+            (0, _) => &mut self.emission,
+            // Light sampled at the first hit arrives before the first bounce
+            // is drawn.
+            (_, None) => &mut self.pending,
+            (1, Some(Lobe::Diffuse)) => &mut self.direct_diffuse,
+            (_, Some(Lobe::Diffuse)) => &mut self.indirect_diffuse,
+            (_, Some(_)) => &mut self.specular,
+        };
+        *layer = *layer + light;
+    }
+
+    /// Counts light sampled at a first hit that never scattered as direct
+    /// diffuse light.
+    pub(crate) fn finish(&mut self) {
+        self.direct_diffuse = self.direct_diffuse + self.pending;
+        self.pending = black();
+    }
+
+    /// Scales the lighting layers by the weight of the camera ray.
+    pub(crate) fn weighted(&self, weight: &Vector3) -> PathAovs {
+        PathAovs {
+            emission: weighted(self.emission, weight),
+            direct_diffuse: weighted(self.direct_diffuse, weight),
+            indirect_diffuse: weighted(self.indirect_diffuse, weight),
+            specular: weighted(self.specular, weight),
+            ..*self
+        }
+    }
+
+    /// Brings the colour layers of a path traced at `wavelengths` to RGB,
+    /// leaving the geometric ones as they are.
+    pub fn to_rgb(&self, wavelengths: &Wavelengths) -> PathAovs {
+        let rgb = |c: &Color| xyz_to_rgb(&wavelengths.to_xyz(c));
+        PathAovs {
+            albedo: rgb(&self.albedo),
+            emission: rgb(&self.emission),
+            direct_diffuse: rgb(&self.direct_diffuse),
+            indirect_diffuse: rgb(&self.indirect_diffuse),
+            specular: rgb(&self.specular),
+            ..*self
+        }
+    }
+
+    /// Every layer by name, with the normal's axes and the depth stored as
+    /// colours.
+    pub fn layers(&self) -> [(&'static str, Color); 9] {
+        let n = self.normal;
+        [
+            ("albedo", self.albedo),
+            (
+                "normal",
+                Color {
+                    r: n.x,
+                    g: n.y,
+                    b: n.z,
+                },
+            ),
+            ("depth", gray(self.depth)),
+            ("emission", self.emission),
+            ("direct_diffuse", self.direct_diffuse),
+            ("indirect_diffuse", self.indirect_diffuse),
+            ("specular", self.specular),
+            ("object_id", self.object_id),
+            ("material_id", self.material_id),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_light_is_split_by_bounces_and_first_lobe() {
+        let mut aovs = PathAovs::default();
+        aovs.add_light(0, gray(1.0));
+        aovs.add_light(1, gray(2.0));
+        aovs.scattered(Lobe::Diffuse, &Vector3::zero());
+        aovs.add_light(1, gray(3.0));
+        aovs.add_light(2, gray(4.0));
+        aovs.scattered(Lobe::Specular, &Vector3::zero());
+        aovs.add_light(3, gray(5.0));
+        aovs.finish();
+
+        assert_eq!(aovs.emission, gray(1.0));
+        assert_eq!(aovs.direct_diffuse, gray(5.0));
+        assert_eq!(aovs.indirect_diffuse, gray(9.0));
+        assert_eq!(aovs.specular, black());
+    }
+}
@@ -0,0 +1,184 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+use crate::distribution::Distribution2D;
+
+/// An error reading an aperture image.
+#[derive(Debug)]
+pub enum ApertureError {
+    Image(image::ImageError),
+    /// The image has no open pixels, so no light can pass.
+    Closed,
+}
+
+impl fmt::Display for ApertureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApertureError::Image(err) => {
+                write!(f, "could not read aperture image: {}", err)
+            }
+            ApertureError::Closed => {
+                write!(f, "aperture image has no open pixels")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ApertureError {}
+
+impl From<image::ImageError> for ApertureError {
+    fn from(err: image::ImageError) -> Self {
+        ApertureError::Image(err)
+    }
+}
+
+/// The shape of a lens opening, which out-of-focus highlights take on.
+/// Points are given with the opening scaled to fit the unit disc.
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    /// A regular polygon with `blades` corners on the unit circle, the
+    /// first at `rotation` radians anticlockwise from the right.
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    Image(ApertureImage),
+}
+
+/// An opening drawn as a grayscale image covering the square around the
+/// unit disc, with white fully open and black closed.
+#[derive(Clone)]
+pub struct ApertureImage {
+    distribution: Distribution2D,
+    open_area: f64,
+}
+
+impl ApertureImage {
+    pub fn new(image: &image::GrayImage) -> Result<Self, ApertureError> {
+        let (width, height) = image.dimensions();
+        let func: Vec<f64> =
+            image.pixels().map(|p| f64::from(p[0]) / 255.0).collect();
+        if func.iter().all(|&f| f == 0.0) {
+            return Err(ApertureError::Closed);
+        }
+        let mean = func.iter().sum::<f64>() / func.len() as f64;
+        Ok(ApertureImage {
+            distribution: Distribution2D::new(
+                &func,
+                width as usize,
+                height as usize,
+            ),
+            open_area: 4.0 * mean,
+        })
+    }
+
+    pub fn open<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> Result<Self, ApertureError> {
+        ApertureImage::new(&image::open(path)?.into_luma8())
+    }
+}
+
+impl Aperture {
+    /// Maps two uniform numbers to a point in the opening, spread evenly
+    /// over its open area, or in proportion to how open an image is.
+    pub fn sample(&self, u1: f64, u2: f64) -> (f64, f64) {
+        match self {
+            Aperture::Circle => {
+                let (r, phi) = (u1.sqrt(), 2.0 * PI * u2);
+                (r * phi.cos(), r * phi.sin())
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // Pick one of the triangles fanning out from the centre,
+                // then a point in it.
+                let n = f64::from((*blades).max(3));
+                let k = (u1 * n).floor().min(n - 1.0);
+                let (a0, a1) = (
+                    rotation + 2.0 * PI * k / n,
+                    rotation + 2.0 * PI * (k + 1.0) / n,
+                );
+                let r = (u1 * n - k).sqrt();
+                (
+                    r * ((1.0 - u2) * a0.cos() + u2 * a1.cos()),
+                    r * ((1.0 - u2) * a0.sin() + u2 * a1.sin()),
+                )
+            }
+            Aperture::Image(image) => {
+                let ((u, v), _) = image.distribution.sample(u1, u2);
+                // Image rows run from the top down.
+                (2.0 * u - 1.0, 1.0 - 2.0 * v)
+            }
+        }
+    }
+
+    /// The open area, for an opening scaled to fit the unit disc.
+    pub fn area(&self) -> f64 {
+        match self {
+            Aperture::Circle => PI,
+            Aperture::Polygon { blades, .. } => {
+                let n = f64::from((*blades).max(3));
+                0.5 * n * (2.0 * PI / n).sin()
+            }
+            Aperture::Image(image) => image.open_area,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polygon_samples_stay_inside() {
+        let aperture = Aperture::Polygon {
+            blades: 6,
+            rotation: 0.3,
+        };
+        let apothem = (PI / 6.0).cos();
+        for i in 0..50 {
+            for j in 0..50 {
+                let (u1, u2) = (f64::from(i) / 50.0, f64::from(j) / 50.0);
+                let (x, y) = aperture.sample(u1, u2);
+                // Every side is `apothem` from the centre, facing between
+                // two corners.
+                let inside = (0..6).all(|k| {
+                    let a = 0.3 + PI / 6.0 * f64::from(2 * k + 1);
+                    x * a.cos() + y * a.sin() <= apothem + 1e-9
+                });
+
+                assert!(inside, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_closed_image_is_rejected() {
+        let black = image::GrayImage::new(4, 4);
+
+        assert!(matches!(
+            ApertureImage::new(&black),
+            Err(ApertureError::Closed)
+        ));
+    }
+
+    #[test]
+    fn test_image_keeps_its_orientation_and_area() {
+        // Only the top left quarter is open.
+        let image = image::GrayImage::from_fn(8, 8, |x, y| {
+            image::Luma([if x < 4 && y < 4 { 255 } else { 0 }])
+        });
+        let aperture = Aperture::Image(ApertureImage::new(&image).unwrap());
+
+        assert!((aperture.area() - 1.0).abs() < 1e-12);
+        for i in 0..20 {
+            for j in 0..20 {
+                let (u1, u2) =
+                    ((f64::from(i) + 0.5) / 20.0, (f64::from(j) + 0.5) / 20.0);
+                let (x, y) = aperture.sample(u1, u2);
+
+                assert!(x <= 0.0 && y >= 0.0, "({}, {})", x, y);
+            }
+        }
+    }
+}
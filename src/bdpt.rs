@@ -0,0 +1,856 @@
+use rand::prelude::*;
+
+use crate::camera::{Camera, CameraRay};
+use crate::color::Color;
+use crate::film::Film;
+use crate::integrator::Integrator;
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::sampler::rng;
+use crate::scene::{HitRecord, Scene};
+use crate::spectrum::Wavelengths;
+use crate::util::{
+    black, escaped, power_heuristic, to_vector, weighted, white,
+};
+use crate::vector::Vector3;
+
+/// Bidirectional path tracing, after Veach's thesis and pbrt. For each
+/// pixel sample a subpath is traced from the camera and another from a
+/// light, and every prefix of one is joined to every prefix of the other.
+/// The ways of building the same path are weighted against each other
+/// with the balance heuristic. Joins that land on the lens directly, light
+/// tracing, are splatted onto the film wherever they land.
+///
+/// Light reaching the camera from lights infinitely far away, such as
+/// skies, can only be found from the camera side, and is weighted between
+/// hitting the light and sampling it as the path tracer does.
+pub struct Bdpt {
+    pub samples: u32,
+    /// The most bounces a joined path may have.
+    pub max_depth: usize,
+    pub spectral: bool,
+}
+
+#[derive(Copy, Clone)]
+enum Kind<'a> {
+    Camera,
+    /// The first vertex of a light subpath, on the light with this index.
+    Light(usize),
+    Surface(HitRecord<'a>),
+}
+
+/// A point on a subpath. `pdf_fwd` is the area density with which the
+/// subpath picked the vertex, and `pdf_rev` the density with which a
+/// subpath from the other end would have picked it.
+#[derive(Copy, Clone)]
+struct Vertex<'a> {
+    kind: Kind<'a>,
+    p: Point,
+    /// The geometric normal, or zero for points not on a surface.
+    normal: Vector3,
+    /// The ray that arrived at the vertex, which carries its wavelengths.
+    ray_in: Ray,
+    /// The subpath's throughput up to the vertex, over its density.
+    beta: Vector3,
+    /// Whether the subpath left the vertex by a specular bounce.
+    delta: bool,
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+/// A camera subpath leaving the scene.
+struct Escape {
+    ray: Ray,
+    beta: Vector3,
+    bsdf_pdf: Option<f64>,
+}
+
+impl<'a> Vertex<'a> {
+    fn on_surface(&self) -> bool {
+        self.normal != Vector3::zero()
+    }
+
+    fn record(&self) -> Option<HitRecord<'a>> {
+        match self.kind {
+            Kind::Surface(rec) => Some(rec),
+            _ => None,
+        }
+    }
+
+    /// The index of the light the vertex lies on, if any.
+    fn light(&self, scene: &Scene) -> Option<usize> {
+        match self.kind {
+            Kind::Light(index) => Some(index),
+            Kind::Surface(rec) if rec.material.is_emissive() => {
+                scene.light_sampler().light_for(rec.object)
+            }
+            _ => None,
+        }
+    }
+
+    fn is_delta_light(&self, scene: &Scene) -> bool {
+        match self.kind {
+            Kind::Light(index) => scene.lights[index].is_delta(),
+            _ => false,
+        }
+    }
+}
+
+/// Turns a solid angle density at `from` into an area density at `to`.
+fn convert_density(pdf: f64, from: &Vertex, to: &Vertex) -> f64 {
+    let w = to.p - from.p;
+    let distance2 = w.norm();
+    if distance2 == 0.0 {
+        return 0.0;
+    }
+    let pdf = pdf / distance2;
+    if to.on_surface() {
+        pdf * w.dot(&to.normal).abs() / distance2.sqrt()
+    } else {
+        pdf
+    }
+}
+
+impl Integrator for Bdpt {
+    fn render(&self, scene: &Scene, camera: &dyn Camera, film: &mut Film) {
+        let mut rng = rng();
+        let (nx, ny) = (film.width, film.height);
+        for y in 0..ny {
+            for x in 0..nx {
+                for _ in 0..self.samples {
+                    let u = (f64::from(x) + rng.gen::<f64>()) / f64::from(nx);
+                    let v = (f64::from(y) + rng.gen::<f64>()) / f64::from(ny);
+
+                    let wavelengths = if self.spectral {
+                        Some(Wavelengths::sample(rng.gen()))
+                    } else {
+                        None
+                    };
+                    let CameraRay { ray, weight } =
+                        camera.generate_ray(u, v, wavelengths);
+                    let mut splats = Vec::new();
+                    let col = self.sample(scene, camera, &ray, &mut splats);
+                    let col = weighted(col, &weight);
+                    match ray.wavelengths {
+                        Some(w) => {
+                            film.add_spectral_sample(x, y, col, &w);
+                            for (s, t, splat) in splats {
+                                film.add_spectral_splat(s, t, splat, &w);
+                            }
+                        }
+                        None => {
+                            film.add_sample(x, y, col);
+                            for (s, t, splat) in splats {
+                                film.add_splat(s, t, splat);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Bdpt {
+    /// Estimates the radiance along the camera ray `ray`. Light tracing
+    /// contributions are pushed onto `splats` with the film position they
+    /// land at.
+    pub(crate) fn sample(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        ray: &Ray,
+        splats: &mut Vec<(f64, f64, Color)>,
+    ) -> Color {
+        let (camera_path, escape) = self.camera_subpath(scene, camera, ray);
+        let light_path =
+            self.light_subpath(scene, &ray.origin, ray.wavelengths);
+
+        let mut radiance = match escape {
+            Some(e) => weighted(escaped(&e.ray, scene, e.bsdf_pdf), &e.beta),
+            None => black(),
+        };
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 2 > self.max_depth
+                {
+                    continue;
+                }
+                let paths = (&light_path[..], &camera_path[..]);
+                if t == 1 {
+                    splats.extend(self.light_tracing(scene, camera, paths, s));
+                } else {
+                    radiance =
+                        radiance + self.connect(scene, camera, paths, s, t);
+                }
+            }
+        }
+        radiance
+    }
+
+    fn camera_subpath<'a>(
+        &self,
+        scene: &Scene<'a>,
+        camera: &dyn Camera,
+        ray: &Ray,
+    ) -> (Vec<Vertex<'a>>, Option<Escape>) {
+        let (_, pdf_dir) = camera.pdf_importance(ray);
+        // A camera that light paths cannot reach is treated as a delta
+        // vertex, so no weight goes to connecting to it.
+        let mut path = vec![Vertex {
+            kind: Kind::Camera,
+            p: ray.origin,
+            normal: Vector3::zero(),
+            ray_in: *ray,
+            beta: white(),
+            delta: pdf_dir == 0.0,
+            pdf_fwd: 1.0,
+            pdf_rev: 0.0,
+        }];
+        let escape = self.walk(
+            scene,
+            *ray,
+            white(),
+            pdf_dir,
+            self.max_depth + 2,
+            &mut path,
+        );
+        (path, escape)
+    }
+
+    /// Starts a subpath on a light picked by the scene's light sampler as
+    /// seen from `eye`, the camera vertex every path of the sample shares.
+    /// Lights that cannot start one leave it empty.
+    fn light_subpath<'a>(
+        &self,
+        scene: &Scene<'a>,
+        eye: &Point,
+        wavelengths: Option<Wavelengths>,
+    ) -> Vec<Vertex<'a>> {
+        let mut path = Vec::new();
+        let (index, pick) = match scene.light_sampler().sample(eye, rng().gen())
+        {
+            Some(picked) => picked,
+            None => return path,
+        };
+        let light = &scene.lights[index];
+        let mut emission = match light.sample_le() {
+            Some(e) if e.pdf_pos > 0.0 && e.pdf_dir > 0.0 => e,
+            _ => return path,
+        };
+        emission.ray.wavelengths = wavelengths;
+
+        let on_surface = light.shape().is_some();
+        let radiance =
+            to_vector(emission.ray.upsample_color(emission.radiance));
+        let beta = radiance / (pick * emission.pdf_pos);
+        path.push(Vertex {
+            kind: Kind::Light(index),
+            p: emission.ray.origin,
+            normal: if on_surface {
+                emission.normal
+            } else {
+                Vector3::zero()
+            },
+            ray_in: emission.ray,
+            beta,
+            delta: false,
+            pdf_fwd: pick * emission.pdf_pos,
+            pdf_rev: 0.0,
+        });
+        let cosine = if on_surface {
+            emission.normal.dot(&emission.ray.direction).abs()
+        } else {
+            1.0
+        };
+        self.walk(
+            scene,
+            emission.ray,
+            beta * (cosine / emission.pdf_dir),
+            emission.pdf_dir,
+            self.max_depth + 1,
+            &mut path,
+        );
+        path
+    }
+
+    /// Extends `path` from its last vertex along `ray`, until it holds
+    /// `max_vertices` or is absorbed. `pdf_dir` is the solid angle density
+    /// with which `ray` was picked. Returns the ray that left the scene, if
+    /// one did.
+    fn walk<'a>(
+        &self,
+        scene: &Scene<'a>,
+        ray: Ray,
+        beta: Vector3,
+        pdf_dir: f64,
+        max_vertices: usize,
+        path: &mut Vec<Vertex<'a>>,
+    ) -> Option<Escape> {
+        let (mut ray, mut beta, mut pdf_fwd) = (ray, beta, pdf_dir);
+        let mut bsdf_pdf = None;
+        while path.len() < max_vertices {
+            let world = scene.world;
+            let rec = match world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    return Some(Escape {
+                        ray,
+                        beta,
+                        bsdf_pdf,
+                    });
+                }
+            };
+            let prev = path.len() - 1;
+            let mut vertex = Vertex {
+                kind: Kind::Surface(rec),
+                p: rec.p,
+                normal: rec.geometric_normal,
+                ray_in: ray,
+                beta,
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            vertex.pdf_fwd = convert_density(pdf_fwd, &path[prev], &vertex);
+            path.push(vertex);
+            if path.len() >= max_vertices {
+                break;
+            }
+
+            let s = match rec.material.scatter(&ray, &rec) {
+                Some(s) => s,
+                None => break,
+            };
+            let current = prev + 1;
+            let direction = s.scattered.direction;
+            let pdf_rev = if s.lobe.is_specular() {
+                path[current].delta = true;
+                bsdf_pdf = None;
+                pdf_fwd = 0.0;
+                0.0
+            } else {
+                let reverse = Ray {
+                    origin: rec.p + direction,
+                    direction: -direction,
+                    ..ray
+                };
+                pdf_fwd = rec.material.pdf(&ray, &rec, &direction);
+                bsdf_pdf = Some(pdf_fwd);
+                rec.material.pdf(&reverse, &rec, &-ray.direction)
+            };
+            path[prev].pdf_rev =
+                convert_density(pdf_rev, &path[current], &path[prev]);
+
+            beta = beta.hadamard(&s.attenuation);
+            if beta == Vector3::zero() {
+                break;
+            }
+            ray = s.scattered;
+        }
+        None
+    }
+
+    /// The area density with which a subpath reaching `v` from `prev`
+    /// would go on to pick `next`.
+    fn pdf(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        v: &Vertex,
+        prev: Option<&Vertex>,
+        next: &Vertex,
+    ) -> f64 {
+        match v.kind {
+            Kind::Light(_) => self.pdf_light(scene, v, next),
+            Kind::Camera => {
+                let ray = Ray {
+                    origin: v.p,
+                    direction: next.p - v.p,
+                    wavelengths: None,
+                };
+                convert_density(camera.pdf_importance(&ray).1, v, next)
+            }
+            Kind::Surface(rec) => {
+                let prev = match prev {
+                    Some(prev) => prev,
+                    None => return 0.0,
+                };
+                let ray_in = Ray {
+                    origin: prev.p,
+                    direction: v.p - prev.p,
+                    ..v.ray_in
+                };
+                let pdf = rec.material.pdf(&ray_in, &rec, &(next.p - v.p));
+                convert_density(pdf, v, next)
+            }
+        }
+    }
+
+    /// The area density with which a light subpath starting at `v` would
+    /// pick `next` as its second vertex.
+    fn pdf_light(&self, scene: &Scene, v: &Vertex, next: &Vertex) -> f64 {
+        match v.light(scene) {
+            Some(index) => {
+                let direction = next.p - v.p;
+                let (_, pdf_dir) =
+                    scene.lights[index].pdf_le(&v.p, &v.normal, &direction);
+                convert_density(pdf_dir, v, next)
+            }
+            None => 0.0,
+        }
+    }
+
+    /// The area density with which a light subpath picked from `eye` would
+    /// start at `v`, heading for `next`.
+    fn pdf_light_origin(
+        &self,
+        scene: &Scene,
+        eye: &Point,
+        v: &Vertex,
+        next: &Vertex,
+    ) -> f64 {
+        match v.light(scene) {
+            Some(index) => {
+                let direction = next.p - v.p;
+                let (pdf_pos, _) =
+                    scene.lights[index].pdf_le(&v.p, &v.normal, &direction);
+                pdf_pos * scene.light_sampler().pmf(eye, index)
+            }
+            None => 0.0,
+        }
+    }
+
+    /// The unweighted contribution of joining the first `s` light vertices
+    /// to the first `t` camera vertices, for `t` of at least two.
+    fn connect(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        paths: (&[Vertex], &[Vertex]),
+        s: usize,
+        t: usize,
+    ) -> Color {
+        let (light_path, camera_path) = paths;
+        let pt = &camera_path[t - 1];
+        let pt_rec = match pt.record() {
+            Some(rec) => rec,
+            None => return black(),
+        };
+
+        if s == 0 {
+            // The camera subpath found a light by itself.
+            let emitted = pt_rec.material.emitted(&pt.ray_in, &pt_rec);
+            if emitted == black() {
+                return black();
+            }
+            let weight = match pt.light(scene) {
+                Some(_) => self.mis_weight(scene, camera, paths, s, t, None),
+                None => 1.0,
+            };
+            return weight
+                * weighted(pt.ray_in.upsample_color(emitted), &pt.beta);
+        }
+
+        if s == 1 {
+            return self.sample_light(scene, camera, paths, t);
+        }
+
+        let qs = &light_path[s - 1];
+        let qs_rec = match qs.record() {
+            Some(rec) => rec,
+            None => return black(),
+        };
+        let w = pt.p - qs.p;
+        let distance2 = w.norm();
+        let distance = distance2.sqrt();
+        let direction = w / distance;
+        let f = qs_rec
+            .material
+            .eval(&qs.ray_in, &qs_rec, &direction)
+            .hadamard(&pt_rec.material.eval(&pt.ray_in, &pt_rec, &-direction));
+        if f == Vector3::zero() || !scene.unoccluded(qs.p, direction, distance)
+        {
+            return black();
+        }
+        let weight = self.mis_weight(scene, camera, paths, s, t, None);
+        let beta = qs.beta.hadamard(&f).hadamard(&pt.beta);
+        Color {
+            r: beta.x,
+            g: beta.y,
+            b: beta.z,
+        } * (weight / distance2)
+    }
+
+    /// Joins the last of the first `t` camera vertices to a point picked on
+    /// one of the scene's lights by its light sampler.
+    fn sample_light(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        paths: (&[Vertex], &[Vertex]),
+        t: usize,
+    ) -> Color {
+        let pt = &paths.1[t - 1];
+        let rec = match pt.record() {
+            Some(rec) => rec,
+            None => return black(),
+        };
+        let sampler = scene.light_sampler();
+        let (index, select_pdf) = match sampler.sample(&pt.p, rng().gen()) {
+            Some(picked) => picked,
+            None => return black(),
+        };
+        let light = &scene.lights[index];
+        let sample = match light.sample(&pt.p) {
+            Some(sample) if sample.pdf > 0.0 => sample,
+            _ => return black(),
+        };
+        let f = rec.material.eval(&pt.ray_in, &rec, &sample.direction);
+        if f == Vector3::zero()
+            || !scene.unoccluded(pt.p, sample.direction, sample.distance)
+        {
+            return black();
+        }
+
+        let light_pdf = sample.pdf * select_pdf;
+        let weight = if light.bounds().is_none() {
+            if light.is_delta() {
+                1.0
+            } else {
+                power_heuristic(
+                    light_pdf,
+                    rec.material.pdf(&pt.ray_in, &rec, &sample.direction),
+                )
+            }
+        } else {
+            let p = pt.p + sample.distance * sample.direction;
+            let towards = Ray {
+                origin: pt.p,
+                direction: sample.direction,
+                wavelengths: None,
+            };
+            let normal = light
+                .shape()
+                .and_then(|shape| {
+                    shape.hit(&towards, 0.001, sample.distance * 1.001 + 1e-3)
+                })
+                .map_or(Vector3::zero(), |rec| rec.geometric_normal);
+            let mut sampled = Vertex {
+                kind: Kind::Light(index),
+                p,
+                normal,
+                ray_in: towards,
+                beta: Vector3::zero(),
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            sampled.pdf_fwd =
+                self.pdf_light_origin(scene, &paths.1[0].p, &sampled, pt);
+            self.mis_weight(scene, camera, paths, 1, t, Some(sampled))
+        };
+        let radiance = pt.ray_in.upsample_color(sample.radiance);
+        weighted(radiance, &pt.beta.hadamard(&f)) * (weight / light_pdf)
+    }
+
+    /// Joins the last of the first `s` light vertices to a point on the
+    /// lens, returning the film position it lands at and its weighted
+    /// contribution.
+    fn light_tracing(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        paths: (&[Vertex], &[Vertex]),
+        s: usize,
+    ) -> Option<(f64, f64, Color)> {
+        let qs = &paths.0[s - 1];
+        let rec = qs.record()?;
+        let lens = camera.sample_lens(&qs.p)?;
+        let w = lens.p - qs.p;
+        let distance = w.length();
+        let direction = w / distance;
+        let f = rec.material.eval(&qs.ray_in, &rec, &direction);
+        if f == Vector3::zero() || !scene.unoccluded(qs.p, direction, distance)
+        {
+            return None;
+        }
+
+        let sampled = Vertex {
+            kind: Kind::Camera,
+            p: lens.p,
+            normal: Vector3::zero(),
+            ray_in: qs.ray_in,
+            beta: white() * (lens.importance.importance / lens.pdf),
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+        let weight = self.mis_weight(scene, camera, paths, s, 1, Some(sampled));
+        let beta = qs.beta.hadamard(&f).hadamard(&sampled.beta) * weight;
+        Some((
+            lens.importance.s,
+            lens.importance.t,
+            Color {
+                r: beta.x,
+                g: beta.y,
+                b: beta.z,
+            },
+        ))
+    }
+
+    /// The balance heuristic weight for joining the first `s` light
+    /// vertices to the first `t` camera vertices, against every other way
+    /// of building the same path. `sampled` replaces the vertex at the
+    /// end of a one-vertex subpath when it was picked afresh for the join.
+    fn mis_weight(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        paths: (&[Vertex], &[Vertex]),
+        s: usize,
+        t: usize,
+        sampled: Option<Vertex>,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let mut light = paths.0[..s].to_vec();
+        let mut cam = paths.1[..t].to_vec();
+        match sampled {
+            Some(v) if s == 1 => light[0] = v,
+            Some(v) if t == 1 => cam[0] = v,
+            _ => {}
+        }
+
+        // The reverse densities at the join, as if the path had been built
+        // by the neighbouring strategies.
+        let pt = cam[t - 1];
+        let pt_minus = if t > 1 { Some(cam[t - 2]) } else { None };
+        if s > 0 {
+            let qs = light[s - 1];
+            let qs_minus = if s > 1 { Some(light[s - 2]) } else { None };
+            cam[t - 1].pdf_rev =
+                self.pdf(scene, camera, &qs, qs_minus.as_ref(), &pt);
+            if let Some(pt_minus) = pt_minus {
+                cam[t - 2].pdf_rev =
+                    self.pdf(scene, camera, &pt, Some(&qs), &pt_minus);
+            }
+            light[s - 1].pdf_rev =
+                self.pdf(scene, camera, &pt, pt_minus.as_ref(), &qs);
+            if let Some(qs_minus) = qs_minus {
+                light[s - 2].pdf_rev =
+                    self.pdf(scene, camera, &qs, Some(&pt), &qs_minus);
+            }
+            light[s - 1].delta = false;
+        } else if let Some(pt_minus) = pt_minus {
+            cam[t - 1].pdf_rev =
+                self.pdf_light_origin(scene, &paths.1[0].p, &pt, &pt_minus);
+            cam[t - 2].pdf_rev = self.pdf_light(scene, &pt, &pt_minus);
+        }
+        cam[t - 1].delta = false;
+
+        // Densities of zero belong to delta vertices, which are skipped.
+        let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap(cam[i].pdf_rev) / remap(cam[i].pdf_fwd);
+            if !cam[i].delta && !cam[i - 1].delta {
+                sum += ri;
+            }
+        }
+        ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+            let delta_before = if i > 0 {
+                light[i - 1].delta
+            } else {
+                light[0].is_delta_light(scene)
+            };
+            if !light[i].delta && !delta_before {
+                sum += ri;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::PerspectiveCamera;
+    use crate::integrator::PathTracer;
+    use crate::material::{DiffuseLight, Lambertian, Material};
+    use crate::scene::{HitList, Quad};
+    use crate::texture::ConstantTexture;
+    use crate::util::PathSettings;
+
+    fn vertex(p: Point, normal: Vector3) -> Vertex<'static> {
+        Vertex {
+            kind: Kind::Camera,
+            p,
+            normal,
+            ray_in: Ray {
+                origin: p,
+                direction: normal,
+                wavelengths: None,
+            },
+            beta: white(),
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_convert_density_applies_distance_and_cosine() {
+        let from = vertex(
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Vector3::zero(),
+        );
+        let up = Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        let to = vertex(
+            Point {
+                x: 0.0,
+                y: 2.0,
+                z: 0.0,
+            },
+            -up,
+        );
+        assert!((convert_density(1.0, &from, &to) - 0.25).abs() < 1e-12);
+
+        let tilted = vertex(
+            to.p,
+            Vector3 {
+                x: 1.0,
+                y: 1.0,
+                z: 0.0,
+            }
+            .normalize(),
+        );
+        let expected = 0.25 * 0.5f64.sqrt();
+        assert!(
+            (convert_density(1.0, &from, &tilted) - expected).abs() < 1e-12
+        );
+        // Points off surfaces, like the camera, have no cosine to apply.
+        assert!((convert_density(1.0, &to, &from) - 0.25).abs() < 1e-12);
+    }
+
+    fn point(x: f64, y: f64, z: f64) -> Point {
+        Point { x, y, z }
+    }
+
+    fn quad(
+        corner: Point,
+        u: Point,
+        v: Point,
+        material: Box<dyn Material>,
+    ) -> Quad {
+        Quad {
+            corner,
+            u: Vector3::from(u),
+            v: Vector3::from(v),
+            material,
+        }
+    }
+
+    /// A closed unit box of grey walls facing inwards, lit by a small quad
+    /// under its ceiling.
+    fn lit_box() -> HitList<'static> {
+        let grey = || -> Box<dyn Material> {
+            Box::new(Lambertian {
+                albedo: Vector3 {
+                    x: 0.4,
+                    y: 0.4,
+                    z: 0.4,
+                },
+            })
+        };
+        let (o, x, y, z) = (
+            Point::origin(),
+            point(1.0, 0.0, 0.0),
+            point(0.0, 1.0, 0.0),
+            point(0.0, 0.0, 1.0),
+        );
+        let mut world = HitList::new();
+        world.push(quad(o, z, x, grey()));
+        world.push(quad(y, x, z, grey()));
+        world.push(quad(o, y, z, grey()));
+        world.push(quad(x, z, y, grey()));
+        world.push(quad(o, x, y, grey()));
+        world.push(quad(z, y, x, grey()));
+        world.push(quad(
+            point(0.2, 0.99, 0.2),
+            point(0.6, 0.0, 0.0),
+            point(0.0, 0.0, 0.6),
+            Box::new(DiffuseLight {
+                emit: Box::new(ConstantTexture {
+                    color: Vector3 {
+                        x: 4.0,
+                        y: 4.0,
+                        z: 4.0,
+                    },
+                }),
+            }),
+        ));
+        world
+    }
+
+    fn image_mean(film: &Film) -> f64 {
+        let mut sum = 0.0;
+        for y in 0..film.height {
+            for x in 0..film.width {
+                let c = film.pixel(x, y);
+                sum += c.r + c.g + c.b;
+            }
+        }
+        sum / f64::from(3 * film.width * film.height)
+    }
+
+    #[test]
+    fn test_matches_the_path_tracer_in_a_lit_box() {
+        let world = lit_box();
+        let scene = Scene::new(&world);
+        let camera = PerspectiveCamera::new(
+            point(0.5, 0.5, 0.05),
+            point(0.5, 0.5, 1.0),
+            Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            80.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        let mut bdpt = Film::new(4, 4);
+        Bdpt {
+            samples: 512,
+            max_depth: 12,
+            spectral: false,
+        }
+        .render(&scene, &camera, &mut bdpt);
+        let mut path = Film::new(4, 4);
+        PathTracer {
+            samples: 512,
+            spectral: false,
+            settings: PathSettings::default(),
+            aovs: false,
+        }
+        .render(&scene, &camera, &mut path);
+
+        let (bdpt, path) = (image_mean(&bdpt), image_mean(&path));
+        assert!(path > 0.0);
+        assert!((bdpt - path).abs() < 0.1 * path, "{} vs {}", bdpt, path);
+    }
+}
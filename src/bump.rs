@@ -0,0 +1,272 @@
+use crate::color::Color;
+use crate::material::{Lobe, Material, Scatter};
+use crate::ray::Ray;
+use crate::scene::HitRecord;
+use crate::texture::{ImageTexture, Texture};
+use crate::vector::Vector3;
+
+/// Builds a record whose shading frame is turned to `normal`, keeping the
+/// tangents perpendicular to it.
+fn with_normal<'a>(rec: &HitRecord<'a>, normal: Vector3) -> HitRecord<'a> {
+    let normal = if normal.dot(&rec.geometric_normal) > 0.0 {
+        normal
+    } else {
+        rec.normal
+    };
+    let dpdu = rec.dpdu - rec.dpdu.dot(&normal) * normal;
+    let bitangent = normal.cross(&dpdu).normalize();
+    let bitangent = if bitangent.dot(&rec.dpdv) < 0.0 {
+        -bitangent
+    } else {
+        bitangent
+    };
+    HitRecord {
+        normal,
+        dpdu,
+        dpdv: rec.dpdv.length() * bitangent,
+        ..*rec
+    }
+}
+
+/// Whether a ray arriving along `ray` and leaving along `direction` changes
+/// sides of the true surface, which a perturbed normal can make a
+/// reflection do.
+fn crosses_surface(ray: &Ray, rec: &HitRecord, direction: &Vector3) -> bool {
+    let incoming = -ray.direction.dot(&rec.geometric_normal);
+    let outgoing = direction.dot(&rec.geometric_normal);
+    incoming * outgoing <= 0.0
+}
+
+/// Scatters off `base` with the shading normal in `shaded`, dropping
+/// reflections that would leak through the true surface and starting the
+/// scattered ray off the geometric surface.
+fn scatter_shaded(
+    base: &dyn Material,
+    ray: &Ray,
+    rec: &HitRecord,
+    shaded: &HitRecord,
+) -> Option<Scatter> {
+    let mut s = base.scatter(ray, shaded)?;
+    let direction = s.scattered.direction;
+    if s.lobe != Lobe::Transmission && crosses_surface(ray, rec, &direction) {
+        return None;
+    }
+    s.scattered.origin = rec.offset_origin(&direction);
+    Some(s)
+}
+
+/// Perturbs the shading normal of `base` with a tangent space normal map,
+/// where red, green and blue hold the `dpdu`, `dpdv` and normal components
+/// remapped from [-1, 1] to [0, 1].
+pub struct NormalMap {
+    pub base: Box<dyn Material>,
+    pub map: Box<dyn Texture>,
+}
+
+impl NormalMap {
+    /// Loads the map from an image file, such as a PNG exported from a
+    /// sculpting or baking tool.
+    pub fn open<P: AsRef<std::path::Path>>(
+        base: Box<dyn Material>,
+        path: P,
+    ) -> image::ImageResult<Self> {
+        Ok(NormalMap {
+            base,
+            map: Box::new(ImageTexture::open(path)?),
+        })
+    }
+
+    fn shade<'a>(&self, rec: &HitRecord<'a>) -> HitRecord<'a> {
+        let texel = self.map.value(rec.u, rec.v, &rec.p);
+        let tangent = rec.dpdu.normalize();
+        let bitangent = rec.normal.cross(&tangent);
+        let bitangent = if bitangent.dot(&rec.dpdv) < 0.0 {
+            -bitangent
+        } else {
+            bitangent
+        };
+        let normal = (2.0 * texel.x - 1.0) * tangent
+            + (2.0 * texel.y - 1.0) * bitangent
+            + (2.0 * texel.z - 1.0) * rec.normal;
+        with_normal(rec, normal.normalize())
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        scatter_shaded(self.base.as_ref(), ray, rec, &self.shade(rec))
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> Vector3 {
+        if crosses_surface(ray, rec, direction) {
+            return Vector3::zero();
+        }
+        self.base.eval(ray, &self.shade(rec), direction)
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> f64 {
+        if crosses_surface(ray, rec, direction) {
+            return 0.0;
+        }
+        self.base.pdf(ray, &self.shade(rec), direction)
+    }
+
+    fn emitted(&self, ray: &Ray, rec: &HitRecord) -> Color {
+        self.base.emitted(ray, rec)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn average_emission(&self) -> f64 {
+        self.base.average_emission()
+    }
+}
+
+/// Perturbs the shading normal of `base` as if the surface were displaced
+/// along its normal by `scale` times the red channel of `height`.
+pub struct BumpMap {
+    pub base: Box<dyn Material>,
+    pub height: Box<dyn Texture>,
+    pub scale: f64,
+}
+
+const BUMP_DELTA: f64 = 1e-3;
+
+impl BumpMap {
+    fn displacement(&self, rec: &HitRecord, u: f64, v: f64) -> f64 {
+        let p = rec.p + (u - rec.u) * rec.dpdu + (v - rec.v) * rec.dpdv;
+        self.scale * self.height.value(u, v, &p).x
+    }
+
+    fn shade<'a>(&self, rec: &HitRecord<'a>) -> HitRecord<'a> {
+        let h = self.displacement(rec, rec.u, rec.v);
+        let dhdu = (self.displacement(rec, rec.u + BUMP_DELTA, rec.v) - h)
+            / BUMP_DELTA;
+        let dhdv = (self.displacement(rec, rec.u, rec.v + BUMP_DELTA) - h)
+            / BUMP_DELTA;
+        let dpdu = rec.dpdu + dhdu * rec.normal;
+        let dpdv = rec.dpdv + dhdv * rec.normal;
+        let normal = dpdu.cross(&dpdv).normalize();
+        let normal = if normal.dot(&rec.normal) < 0.0 {
+            -normal
+        } else {
+            normal
+        };
+        with_normal(rec, normal)
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        scatter_shaded(self.base.as_ref(), ray, rec, &self.shade(rec))
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> Vector3 {
+        if crosses_surface(ray, rec, direction) {
+            return Vector3::zero();
+        }
+        self.base.eval(ray, &self.shade(rec), direction)
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> f64 {
+        if crosses_surface(ray, rec, direction) {
+            return 0.0;
+        }
+        self.base.pdf(ray, &self.shade(rec), direction)
+    }
+
+    fn emitted(&self, ray: &Ray, rec: &HitRecord) -> Color {
+        self.base.emitted(ray, rec)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn average_emission(&self) -> f64 {
+        self.base.average_emission()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::point::Point;
+    use crate::scene::{Hit, Sphere};
+    use crate::texture::ConstantTexture;
+
+    fn sphere() -> Sphere {
+        Sphere::new(
+            Point::origin(),
+            1.0,
+            Box::new(Lambertian {
+                albedo: Vector3::zero(),
+            }),
+        )
+    }
+
+    fn hit(sphere: &Sphere) -> (Ray, HitRecord<'_>) {
+        let origin = Point {
+            x: 2.0,
+            y: 1.0,
+            z: 0.5,
+        };
+        let ray = Ray {
+            origin,
+            direction: Point::origin() - origin,
+            wavelengths: None,
+        };
+        let rec = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        (ray, rec)
+    }
+
+    fn normal_map(texel: Vector3) -> NormalMap {
+        NormalMap {
+            base: Box::new(Lambertian {
+                albedo: Vector3 {
+                    x: 0.5,
+                    y: 0.5,
+                    z: 0.5,
+                },
+            }),
+            map: Box::new(ConstantTexture { color: texel }),
+        }
+    }
+
+    #[test]
+    fn test_flat_normal_map_keeps_the_normal() {
+        let sphere = sphere();
+        let (_, rec) = hit(&sphere);
+        let shaded = normal_map(Vector3 {
+            x: 0.5,
+            y: 0.5,
+            z: 1.0,
+        })
+        .shade(&rec);
+
+        assert!((shaded.normal - rec.normal).length() < 1e-9);
+        assert!(shaded.dpdu.dot(&shaded.normal).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_directions_below_the_surface_have_no_density() {
+        let sphere = sphere();
+        let (ray, rec) = hit(&sphere);
+        let map = normal_map(Vector3 {
+            x: 0.95,
+            y: 0.5,
+            z: 0.6,
+        });
+        // Below the true surface, yet above the steeply tilted shading
+        // normal.
+        let direction =
+            (rec.dpdu.normalize() - 0.1 * rec.geometric_normal).normalize();
+
+        assert!(map.shade(&rec).normal.dot(&direction) > 0.0);
+        assert_eq!(map.eval(&ray, &rec, &direction), Vector3::zero());
+        assert_eq!(map.pdf(&ray, &rec, &direction), 0.0);
+    }
+}
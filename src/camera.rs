@@ -1,20 +1,128 @@
 use rand::prelude::*;
 
+use crate::aperture::Aperture;
+use crate::lens::{LensError, LensPrescription};
 use crate::point::Point;
 use crate::ray::Ray;
+use crate::sampler::rng;
+use crate::spectrum::Wavelengths;
 use crate::vector::Vector3;
 
-pub struct Camera {
+/// Where a ray leaving the lens lands on the film, with `s` and `t` as
+/// taken by `Camera::get_ray`, and the importance the camera gives it.
+pub struct Importance {
+    pub s: f64,
+    pub t: f64,
+    pub importance: f64,
+}
+
+/// A ray leaving the camera, and the weight each channel of the light it
+/// brings back is given. A weight of zero means the lens blocked it.
+pub struct CameraRay {
+    pub ray: Ray,
+    pub weight: Vector3,
+}
+
+/// A point on the lens chosen to look at a point in the scene.
+pub struct LensSample {
+    pub p: Point,
+    pub importance: Importance,
+    /// Solid angle density of the direction towards `p`, as seen from the
+    /// point in the scene.
+    pub pdf: f64,
+}
+
+/// A projection from film positions to rays. `s` and `t` run from zero to
+/// one across the film, left to right and bottom to top.
+///
+/// `get_ray` gives the geometry of a ray alone. Integrators call
+/// `generate_ray`, which lenses override to block rays or to bend them
+/// differently by wavelength.
+///
+/// Integrators that trace from the lights, like bidirectional path
+/// tracing, also need to find where a ray from the scene meets the film.
+/// Cameras that cannot be reached that way, because all their rays share
+/// one direction, leave those methods at their defaults.
+pub trait Camera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray;
+
+    /// The ray through `(s, t)` carrying `wavelengths`, when rendering
+    /// spectrally.
+    fn generate_ray(
+        &self,
+        s: f64,
+        t: f64,
+        wavelengths: Option<Wavelengths>,
+    ) -> CameraRay {
+        let ray = Ray {
+            wavelengths,
+            ..self.get_ray(s, t)
+        };
+        CameraRay {
+            ray,
+            weight: Vector3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+        }
+    }
+
+    /// The film position and importance of `ray`, which leaves a point on
+    /// the lens, or `None` if it misses the film.
+    fn importance(&self, _ray: &Ray) -> Option<Importance> {
+        None
+    }
+
+    /// The densities with which `get_ray` picks the origin of `ray` on the
+    /// lens, by area, and its direction, by solid angle.
+    fn pdf_importance(&self, _ray: &Ray) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+
+    /// Picks a point on the lens that could see `p`.
+    fn sample_lens(&self, _p: &Point) -> Option<LensSample> {
+        None
+    }
+
+    /// The camera for one eye of a stereo pair, moved `offset` to the
+    /// right, or to the left if negative, and aimed so that the two eyes'
+    /// views meet `convergence` in front. `None` if the projection has no
+    /// stereo form.
+    fn eye(&self, _offset: f64, _convergence: f64) -> Option<Box<dyn Camera>> {
+        None
+    }
+}
+
+/// A pinhole or thin lens camera with a vertical field of view, focused
+/// `focus_dist` in front of it.
+///
+/// The lens opening has the shape of `aperture`. With `cat_eye` above
+/// zero, the front of the lens barrel clips the opening off the axis: its
+/// outline is shifted by `cat_eye` lens radii per unit of distance from
+/// the film centre, where the top and bottom edges are one unit away, and
+/// light only passes through where both overlap. `chromatic_aberration`
+/// is the relative change in focus distance and magnification per 100 nm
+/// of wavelength away from 550 nm, and like dispersion only shows when
+/// rendering spectrally. Light traced from the lights towards the camera
+/// by bidirectional methods meets a lens without aberration.
+#[derive(Clone)]
+pub struct PerspectiveCamera {
     origin: Point,
     start: Vector3,
     horizontal: Vector3,
     vertical: Vector3,
     u: Vector3,
     v: Vector3,
+    w: Vector3,
     lens_radius: f64,
+    focus_dist: f64,
+    pub aperture: Aperture,
+    pub cat_eye: f64,
+    pub chromatic_aberration: f64,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         origin: Point,
         look_at: Point,
@@ -34,47 +142,710 @@ impl Camera {
         let start = focus_dist * (-(half_width * u + half_height * v) + w);
         let horizontal = 2.0 * half_width * focus_dist * u;
         let vertical = 2.0 * half_height * focus_dist * v;
-        Camera {
+        PerspectiveCamera {
             origin,
             start,
             horizontal,
             vertical,
             u,
             v,
+            w,
             lens_radius,
+            focus_dist,
+            aperture: Aperture::Circle,
+            cat_eye: 0.0,
+            chromatic_aberration: 0.0,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let lens_pt = self.lens_radius * random_in_unit_disc();
-        let offset = self.u * lens_pt.x + self.v * lens_pt.y;
+    /// The direction the camera looks in, which is also the normal of its
+    /// lens.
+    pub fn forward(&self) -> Vector3 {
+        self.w
+    }
+
+    /// The open area of the lens, or one for a pinhole.
+    fn lens_area(&self) -> f64 {
+        if self.lens_radius > 0.0 {
+            self.aperture.area() * self.lens_radius * self.lens_radius
+        } else {
+            1.0
+        }
+    }
+
+    /// A point on the lens, relative to its centre.
+    fn sample_aperture(&self) -> Vector3 {
+        let mut rng = rng();
+        let (x, y) = self.aperture.sample(rng.gen(), rng.gen());
+        self.lens_radius * (x * self.u + y * self.v)
+    }
+
+    /// Whether light through `offset` from the lens centre reaches film
+    /// position `(s, t)` past the front of the barrel.
+    fn unclipped(&self, offset: &Vector3, s: f64, t: f64) -> bool {
+        if self.cat_eye <= 0.0 || self.lens_radius <= 0.0 {
+            return true;
+        }
+        let aspect = self.horizontal.length() / self.vertical.length();
+        let shift = self.cat_eye * self.lens_radius;
+        let dx = offset.dot(&self.u) - shift * (2.0 * s - 1.0) * aspect;
+        let dy = offset.dot(&self.v) - shift * (2.0 * t - 1.0);
+        dx * dx + dy * dy <= self.lens_radius * self.lens_radius
+    }
+
+    /// The ray from `offset` on the lens towards film position `(s, t)`,
+    /// with focus distance and magnification scaled by `scale`.
+    fn ray_through(&self, offset: Vector3, s: f64, t: f64, scale: f64) -> Ray {
+        let axis = self.focus_dist * self.w;
+        let target = self.start + s * self.horizontal + t * self.vertical;
         Ray {
             origin: self.origin + offset,
-            direction: self.start + s * self.horizontal + t * self.vertical
-                - offset,
+            direction: scale * (axis + scale * (target - axis)) - offset,
+            wavelengths: None,
         }
     }
+
+    /// The area of the film scaled to one unit in front of the lens.
+    fn film_area(&self) -> f64 {
+        self.horizontal.length() * self.vertical.length()
+            / (self.focus_dist * self.focus_dist)
+    }
 }
 
-fn random_in_unit_disc() -> Point {
-    let mut rng = thread_rng();
-    loop {
-        let p =
-            2.0 * Vector3 {
-                x: rng.gen(),
-                y: rng.gen(),
-                z: 0.0,
-            } - Vector3 {
-                x: 1.0,
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        self.ray_through(self.sample_aperture(), s, t, 1.0)
+    }
+
+    fn generate_ray(
+        &self,
+        s: f64,
+        t: f64,
+        wavelengths: Option<Wavelengths>,
+    ) -> CameraRay {
+        let offset = self.sample_aperture();
+        let mut weight = Vector3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+        if !self.unclipped(&offset, s, t) {
+            weight = Vector3::zero();
+        }
+        let (scale, wavelengths) = match wavelengths {
+            Some(w) if self.chromatic_aberration != 0.0 => {
+                // Each wavelength focuses differently, so only the hero
+                // can follow this ray.
+                let (w, terminate) = w.terminate_secondary();
+                weight = weight.hadamard(&terminate);
+                let shift = (w.hero() - 550.0) / 100.0;
+                (1.0 + self.chromatic_aberration * shift, Some(w))
+            }
+            _ => (1.0, wavelengths),
+        };
+        CameraRay {
+            ray: Ray {
+                wavelengths,
+                ..self.ray_through(offset, s, t, scale)
+            },
+            weight,
+        }
+    }
+
+    fn importance(&self, ray: &Ray) -> Option<Importance> {
+        let direction = ray.direction.normalize();
+        let cos_theta = direction.dot(&self.w);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let focus = ray.origin + (self.focus_dist / cos_theta) * direction;
+        let offset = focus - self.origin - self.start;
+        let s = offset.dot(&self.horizontal) / self.horizontal.norm();
+        let t = offset.dot(&self.vertical) / self.vertical.norm();
+        if !(0.0..1.0).contains(&s)
+            || !(0.0..1.0).contains(&t)
+            || !self.unclipped(&(ray.origin - self.origin), s, t)
+        {
+            return None;
+        }
+        let cos2 = cos_theta * cos_theta;
+        Some(Importance {
+            s,
+            t,
+            importance: 1.0
+                / (self.film_area() * self.lens_area() * cos2 * cos2),
+        })
+    }
+
+    fn pdf_importance(&self, ray: &Ray) -> (f64, f64) {
+        if self.importance(ray).is_none() {
+            return (0.0, 0.0);
+        }
+        let cos_theta = ray.direction.normalize().dot(&self.w);
+        (
+            1.0 / self.lens_area(),
+            1.0 / (self.film_area() * cos_theta * cos_theta * cos_theta),
+        )
+    }
+
+    fn sample_lens(&self, p: &Point) -> Option<LensSample> {
+        let lens = self.origin + self.sample_aperture();
+        let to_p = *p - lens;
+        let distance = to_p.length();
+        let ray = Ray {
+            origin: lens,
+            direction: to_p / distance,
+            wavelengths: None,
+        };
+        let importance = self.importance(&ray)?;
+        let cosine = ray.direction.dot(&self.w);
+        Some(LensSample {
+            p: lens,
+            importance,
+            pdf: distance * distance / (cosine * self.lens_area()),
+        })
+    }
+
+    /// Shifts the film window against the eye, so that both eyes frame
+    /// the same rectangle at the convergence distance and look along
+    /// parallel axes.
+    fn eye(&self, offset: f64, convergence: f64) -> Option<Box<dyn Camera>> {
+        let shift = offset * self.u;
+        Some(Box::new(PerspectiveCamera {
+            origin: self.origin + shift,
+            start: self.start - (self.focus_dist / convergence) * shift,
+            ..self.clone()
+        }))
+    }
+}
+
+/// A camera whose rays all run parallel, leaving a `width` by `height`
+/// rectangle centred on `origin` and facing `look_at`, so sizes do not
+/// shrink with distance.
+pub struct OrthographicCamera {
+    start: Point,
+    horizontal: Vector3,
+    vertical: Vector3,
+    w: Vector3,
+}
+
+impl OrthographicCamera {
+    pub fn new(
+        origin: Point,
+        look_at: Point,
+        v_up: Vector3,
+        width: f64,
+        height: f64,
+    ) -> Self {
+        let (u, v, w) = look_frame(origin, look_at, v_up);
+        let horizontal = width * u;
+        let vertical = height * v;
+        OrthographicCamera {
+            start: origin - 0.5 * (horizontal + vertical),
+            horizontal,
+            vertical,
+            w,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        Ray {
+            origin: self.start + s * self.horizontal + t * self.vertical,
+            direction: self.w,
+            wavelengths: None,
+        }
+    }
+}
+
+/// The right, up and forward axes of a camera at `origin` facing
+/// `look_at`.
+fn look_frame(
+    origin: Point,
+    look_at: Point,
+    v_up: Vector3,
+) -> (Vector3, Vector3, Vector3) {
+    let w = (look_at - origin).normalize();
+    let u = w.cross(&v_up).normalize();
+    (u, u.cross(&w), w)
+}
+
+/// How a fisheye lens spaces angles from its axis across the film.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance from the centre grows with the angle itself.
+    Equidistant,
+    /// Distance from the centre grows with the sine of half the angle, so
+    /// equal solid angles cover equal areas.
+    Equisolid,
+}
+
+/// A fisheye camera whose image circle, `fov` degrees across, fills the
+/// height of the film. Beyond the circle, in the corners of a wide film,
+/// the mapping carries on out to directions behind the camera.
+pub struct FisheyeCamera {
+    origin: Point,
+    u: Vector3,
+    v: Vector3,
+    w: Vector3,
+    half_fov: f64,
+    aspect_ratio: f64,
+    mapping: FisheyeMapping,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        origin: Point,
+        look_at: Point,
+        v_up: Vector3,
+        fov: f64,
+        aspect_ratio: f64,
+        mapping: FisheyeMapping,
+    ) -> Self {
+        let (u, v, w) = look_frame(origin, look_at, v_up);
+        FisheyeCamera {
+            origin,
+            u,
+            v,
+            w,
+            half_fov: fov.to_radians() / 2.0,
+            aspect_ratio,
+            mapping,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        let x = (2.0 * s - 1.0) * self.aspect_ratio;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => {
+                2.0 * (r * (self.half_fov / 2.0).sin()).min(1.0).asin()
+            }
+        }
+        .min(std::f64::consts::PI);
+        let phi = y.atan2(x);
+        Ray {
+            origin: self.origin,
+            direction: theta.sin() * (phi.cos() * self.u + phi.sin() * self.v)
+                + theta.cos() * self.w,
+            wavelengths: None,
+        }
+    }
+}
+
+/// A 360° by 180° panorama in latitude and longitude, with `look_at` in
+/// the middle of the film and `v_up` at the top.
+///
+/// As one eye of an omni-directional stereo pair, each ray leaves from a
+/// circle around the origin instead, offset to the side of its own
+/// longitude. The offset shrinks towards the poles, where the eyes would
+/// otherwise swap over.
+#[derive(Copy, Clone)]
+pub struct EquirectangularCamera {
+    origin: Point,
+    u: Vector3,
+    v: Vector3,
+    w: Vector3,
+    eye_offset: f64,
+    convergence: f64,
+}
+
+impl EquirectangularCamera {
+    pub fn new(origin: Point, look_at: Point, v_up: Vector3) -> Self {
+        let (u, v, w) = look_frame(origin, look_at, v_up);
+        EquirectangularCamera {
+            origin,
+            u,
+            v,
+            w,
+            eye_offset: 0.0,
+            convergence: 1.0,
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        let longitude = (s - 0.5) * 2.0 * std::f64::consts::PI;
+        let latitude = (t - 0.5) * std::f64::consts::PI;
+        let ahead = longitude.sin() * self.u + longitude.cos() * self.w;
+        let right = longitude.cos() * self.u - longitude.sin() * self.w;
+        let direction = latitude.cos() * ahead + latitude.sin() * self.v;
+        let offset = self.eye_offset * latitude.cos() * right;
+        Ray {
+            origin: self.origin + offset,
+            direction: self.convergence * direction - offset,
+            wavelengths: None,
+        }
+    }
+
+    fn eye(&self, offset: f64, convergence: f64) -> Option<Box<dyn Camera>> {
+        Some(Box::new(EquirectangularCamera {
+            eye_offset: offset,
+            convergence,
+            ..*self
+        }))
+    }
+}
+
+/// The six 90° faces of a cube map around `origin`, side by side in one
+/// strip in the order +x, -x, +y, -y, +z, -z, so the film should be six
+/// times as wide as it is high. The side faces have +y up, and the faces
+/// along y have -z and +z up respectively.
+pub struct CubemapCamera {
+    pub origin: Point,
+}
+
+impl Camera for CubemapCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        let axis = |x: f64, y: f64, z: f64| Vector3 { x, y, z };
+        let face = ((s * 6.0) as usize).min(5);
+        let (forward, up) = match face {
+            0 => (axis(1.0, 0.0, 0.0), axis(0.0, 1.0, 0.0)),
+            1 => (axis(-1.0, 0.0, 0.0), axis(0.0, 1.0, 0.0)),
+            2 => (axis(0.0, 1.0, 0.0), axis(0.0, 0.0, -1.0)),
+            3 => (axis(0.0, -1.0, 0.0), axis(0.0, 0.0, 1.0)),
+            4 => (axis(0.0, 0.0, 1.0), axis(0.0, 1.0, 0.0)),
+            _ => (axis(0.0, 0.0, -1.0), axis(0.0, 1.0, 0.0)),
+        };
+        let a = 2.0 * (s * 6.0 - face as f64) - 1.0;
+        let b = 2.0 * t - 1.0;
+        Ray {
+            origin: self.origin,
+            direction: forward + a * forward.cross(&up) + b * up,
+            wavelengths: None,
+        }
+    }
+}
+
+/// Millimetres of lens and film per unit of scene, which is in metres.
+const MM_PER_UNIT: f64 = 1000.0;
+
+/// A camera that follows each ray through the surfaces of a real lens, so
+/// that the image shows the lens's own distortion and vignetting, and its
+/// field of view shifts as it focuses. The film, `film_diagonal`
+/// millimetres across, is centred on `origin` and faces `look_at`, and the
+/// lens is moved to focus `focus_dist` in front of it.
+///
+/// Rays are aimed evenly over the rear element. Those that the lens
+/// blocks carry no weight, and the rest fall off with the fourth power of
+/// the cosine of their angle to the axis, scaled so that the centre of the
+/// film is as bright as through the other cameras.
+pub struct RealisticCamera {
+    origin: Point,
+    u: Vector3,
+    v: Vector3,
+    w: Vector3,
+    lens: LensPrescription,
+    film_width: f64,
+    film_height: f64,
+    exposure: f64,
+}
+
+impl RealisticCamera {
+    pub fn new(
+        origin: Point,
+        look_at: Point,
+        v_up: Vector3,
+        mut lens: LensPrescription,
+        film_diagonal: f64,
+        aspect_ratio: f64,
+        focus_dist: f64,
+    ) -> Result<Self, LensError> {
+        let (u, v, w) = look_frame(origin, look_at, v_up);
+        lens.focus(focus_dist * MM_PER_UNIT)?;
+        let film_height =
+            film_diagonal / (1.0 + aspect_ratio * aspect_ratio).sqrt();
+        let mut camera = RealisticCamera {
+            origin,
+            u,
+            v,
+            w,
+            lens,
+            film_width: film_height * aspect_ratio,
+            film_height,
+            exposure: 1.0,
+        };
+
+        let n = 64;
+        let total: f64 = (0..n * n)
+            .filter_map(|i| {
+                let u1 = (f64::from(i % n) + 0.5) / f64::from(n);
+                let u2 = (f64::from(i / n) + 0.5) / f64::from(n);
+                camera.trace_from(0.0, 0.0, u1, u2)
+            })
+            .map(|(_, weight)| weight)
+            .sum();
+        if total == 0.0 {
+            return Err(LensError::Design(
+                "no light reaches the centre of the film".to_string(),
+            ));
+        }
+        camera.exposure = f64::from(n * n) / total;
+        Ok(camera)
+    }
+
+    /// The ray in lens space leaving the front of the lens, for light
+    /// from film point `(x, y)` aimed at the point of the rear element
+    /// that `u1` and `u2` pick, and its weight before exposure.
+    fn trace_from(
+        &self,
+        x: f64,
+        y: f64,
+        u1: f64,
+        u2: f64,
+    ) -> Option<(Ray, f64)> {
+        let rear = self.lens.rear();
+        let (rx, ry) = Aperture::Circle.sample(u1, u2);
+        let film = Point { x, y, z: 0.0 };
+        let target = Point {
+            x: rear.aperture_radius * rx,
+            y: rear.aperture_radius * ry,
+            z: rear.thickness,
+        };
+        let ray = Ray {
+            origin: film,
+            direction: target - film,
+            wavelengths: None,
+        };
+        let cos_theta = ray.direction.normalize().z;
+        let out = self.lens.trace(&ray, true)?;
+        Some((out, cos_theta.powi(4)))
+    }
+
+    /// The film point that `(s, t)` falls on, which the lens turns upside
+    /// down.
+    fn film_point(&self, s: f64, t: f64) -> (f64, f64) {
+        ((0.5 - s) * self.film_width, (0.5 - t) * self.film_height)
+    }
+
+    fn to_world(&self, ray: &Ray) -> Ray {
+        let axes = |a: Vector3| a.x * self.u + a.y * self.v + a.z * self.w;
+        Ray {
+            origin: self.origin
+                + axes(ray.origin - Point::origin()) / MM_PER_UNIT,
+            direction: axes(ray.direction),
+            wavelengths: ray.wavelengths,
+        }
+    }
+
+    /// The ray straight out along the axis from film point `(x, y)`,
+    /// standing in for light that cannot get through.
+    fn blocked(&self, x: f64, y: f64) -> Ray {
+        Ray {
+            origin: self.origin + (x * self.u + y * self.v) / MM_PER_UNIT,
+            direction: self.w,
+            wavelengths: None,
+        }
+    }
+}
+
+impl Camera for RealisticCamera {
+    /// Aims again until a ray gets through, giving up in corners that the
+    /// lens leaves dark.
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        let (x, y) = self.film_point(s, t);
+        let mut rng = rng();
+        (0..16)
+            .find_map(|_| self.trace_from(x, y, rng.gen(), rng.gen()))
+            .map_or_else(|| self.blocked(x, y), |(ray, _)| self.to_world(&ray))
+    }
+
+    fn generate_ray(
+        &self,
+        s: f64,
+        t: f64,
+        wavelengths: Option<Wavelengths>,
+    ) -> CameraRay {
+        let (x, y) = self.film_point(s, t);
+        let mut rng = rng();
+        let (ray, weight) = match self.trace_from(x, y, rng.gen(), rng.gen()) {
+            Some((ray, weight)) => {
+                (self.to_world(&ray), self.exposure * weight)
+            }
+            None => (self.blocked(x, y), 0.0),
+        };
+        CameraRay {
+            ray: Ray { wavelengths, ..ray },
+            weight: Vector3 {
+                x: weight,
+                y: weight,
+                z: weight,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orthographic_rays_are_parallel() {
+        let camera = OrthographicCamera::new(
+            Point::origin(),
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            Vector3 {
+                x: 0.0,
                 y: 1.0,
                 z: 0.0,
-            };
-        if p.norm() < 1.0 {
-            return Point {
-                x: p.x,
-                y: p.y,
-                z: p.z,
-            };
+            },
+            4.0,
+            2.0,
+        );
+        let corner = camera.get_ray(0.0, 0.0);
+        let opposite = camera.get_ray(1.0, 1.0);
+
+        assert_eq!(corner.direction, opposite.direction);
+        assert_eq!(
+            opposite.origin - corner.origin,
+            Vector3 {
+                x: 4.0,
+                y: 2.0,
+                z: 0.0,
+            }
+        );
+    }
+
+    fn up() -> Vector3 {
+        Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_equirectangular_covers_the_sphere() {
+        let look_at = Point {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
         };
+        let camera = EquirectangularCamera::new(Point::origin(), look_at, up());
+        let centre = camera.get_ray(0.5, 0.5).direction;
+        let behind = camera.get_ray(0.0, 0.5).direction;
+        let top = camera.get_ray(0.3, 1.0).direction;
+
+        assert!((centre - (look_at - Point::origin())).length() < 1e-9);
+        assert!((behind.z - 1.0).abs() < 1e-9);
+        assert!((top.y - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fisheye_edge_is_half_the_field_of_view() {
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid]
+        {
+            let camera = FisheyeCamera::new(
+                Point::origin(),
+                Point {
+                    x: 0.0,
+                    y: 0.0,
+                    z: -1.0,
+                },
+                up(),
+                180.0,
+                1.0,
+                mapping,
+            );
+            let edge = camera.get_ray(0.5, 1.0).direction.normalize();
+            let halfway = camera.get_ray(0.5, 0.75).direction.normalize();
+
+            assert!((edge.y - 1.0).abs() < 1e-9);
+            assert!(halfway.y > 0.0 && halfway.z < 0.0);
+        }
+    }
+
+    #[test]
+    fn test_eyes_converge() {
+        let look_at = Point {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
+        };
+        let perspective = PerspectiveCamera::new(
+            Point::origin(),
+            look_at,
+            up(),
+            40.0,
+            2.0,
+            0.0,
+            1.0,
+        );
+        let panorama =
+            EquirectangularCamera::new(Point::origin(), look_at, up());
+        for camera in [&perspective as &dyn Camera, &panorama] {
+            let left = camera.eye(-0.03, 5.0).unwrap().get_ray(0.5, 0.5);
+            let right = camera.eye(0.03, 5.0).unwrap().get_ray(0.5, 0.5);
+            // Where each ray crosses the plane 5 in front, at z = -5.
+            let meet = |ray: &Ray| ray.at(-5.0 / ray.direction.z);
+
+            assert!((left.origin.x + 0.03).abs() < 1e-9);
+            assert!((meet(&left) - meet(&right)).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_cat_eye_clips_the_lens_towards_the_corners() {
+        let mut camera = PerspectiveCamera::new(
+            Point::origin(),
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            up(),
+            40.0,
+            1.0,
+            2.0,
+            1.0,
+        );
+        let side = |x: f64| Vector3 { x, y: 0.0, z: 0.0 };
+        assert!(camera.unclipped(&side(-1.0), 1.0, 1.0));
+
+        camera.cat_eye = 0.5;
+        // The middle of the film sees the whole lens, and a corner loses
+        // the edge of the lens away from it.
+        assert!(camera.unclipped(&side(-1.0), 0.5, 0.5));
+        assert!(camera.unclipped(&side(1.0), 0.5, 0.5));
+        assert!(!camera.unclipped(&side(-1.0), 1.0, 1.0));
+        assert!(camera.unclipped(&side(0.8), 1.0, 1.0));
+        assert!(!camera.unclipped(&side(0.8), 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_realistic_camera_focuses_where_asked() {
+        let camera = RealisticCamera::new(
+            Point::origin(),
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            up(),
+            LensPrescription::double_gauss(),
+            35.0,
+            1.5,
+            2.0,
+        )
+        .unwrap();
+        // Rays near the axis from the film centre meet again on the axis
+        // at the focus distance, in millimetres.
+        for u2 in [0.0, 0.5] {
+            let (ray, _) = camera.trace_from(0.0, 0.0, 1e-4, u2).unwrap();
+            let z =
+                ray.origin.z - ray.origin.x / ray.direction.x * ray.direction.z;
+
+            assert!((z - 2000.0).abs() < 2.0, "{}", z);
+        }
     }
 }
@@ -0,0 +1,202 @@
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::film::Film;
+use crate::integrator::{Integrator, trace_pixels};
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, Scene};
+use crate::util::{gray, random_cosine_direction};
+use crate::vector::Vector3;
+
+/// Ambient occlusion: the fraction of cosine-weighted directions above the
+/// first surface a camera ray hits that leave without meeting anything
+/// within `radius`. `samples` camera rays go through each pixel and each
+/// casts `rays` occlusion rays. Rays that miss the scene count as open.
+pub struct AmbientOcclusion {
+    pub samples: u32,
+    pub rays: u32,
+    pub radius: f64,
+}
+
+impl AmbientOcclusion {
+    fn occlusion(&self, scene: &Scene, ray: &Ray) -> Color {
+        let rec = match scene.world.hit(ray, 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return gray(1.0),
+        };
+        let normal = if rec.normal.dot(&ray.direction) > 0.0 {
+            -rec.normal
+        } else {
+            rec.normal
+        };
+        let onb = Onb::from_w(normal);
+        let open = (0..self.rays)
+            .filter(|_| {
+                let direction = onb.local(&random_cosine_direction());
+                let probe = Ray {
+                    origin: rec.offset_origin(&direction),
+                    direction,
+                    ..*ray
+                };
+                scene.world.hit(&probe, 0.0, self.radius).is_none()
+            })
+            .count();
+        gray(open as f64 / f64::from(self.rays.max(1)))
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn render(&self, scene: &Scene, camera: &dyn Camera, film: &mut Film) {
+        trace_pixels(camera, film, self.samples, |ray| {
+            self.occlusion(scene, ray)
+        });
+    }
+}
+
+/// What the `DebugViewer` integrator shows of the first surface each camera ray
+/// hits.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DebugView {
+    /// The shading normal, mapped from [-1, 1] to [0, 1] per axis.
+    ShadingNormal,
+    GeometricNormal,
+    /// Distance along the ray, black at the camera and white at `far`.
+    Depth {
+        far: f64,
+    },
+    /// Texture coordinates in the red and green channels.
+    Uv,
+    /// Barycentric weights of the three vertices of a hit triangle, with
+    /// other shapes left black.
+    Barycentrics,
+    /// A flat colour for each distinct material.
+    MaterialId,
+    /// A flat colour for each object, with a whole mesh counting as one.
+    ObjectId,
+    /// White wherever something was hit.
+    HitMask,
+}
+
+/// A non-physical view of the scene's geometry for debugging, tracing
+/// `samples` camera rays through each pixel and showing `view` at the
+/// first hit. Misses are black.
+pub struct DebugViewer {
+    pub samples: u32,
+    pub view: DebugView,
+}
+
+fn normal_color(n: &Vector3) -> Color {
+    Color {
+        r: 0.5 * (n.x + 1.0),
+        g: 0.5 * (n.y + 1.0),
+        b: 0.5 * (n.z + 1.0),
+    }
+}
+
+/// A colour that stays the same for the same address and is unlikely to
+/// match that of a nearby one.
+pub(crate) fn id_color(address: usize) -> Color {
+    // The finaliser of SplitMix64.
+    let mut x = address as u64;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    let channel = |shift: u32| ((x >> shift) & 0xff) as f64 / 255.0;
+    Color {
+        r: channel(0),
+        g: channel(8),
+        b: channel(16),
+    }
+}
+
+impl DebugView {
+    pub fn shade(&self, ray: &Ray, rec: &HitRecord) -> Color {
+        match *self {
+            DebugView::ShadingNormal => normal_color(&rec.normal),
+            DebugView::GeometricNormal => normal_color(&rec.geometric_normal),
+            DebugView::Depth { far } => {
+                gray((rec.t * ray.direction.length() / far).min(1.0))
+            }
+            DebugView::Uv => Color {
+                r: rec.u,
+                g: rec.v,
+                b: 0.0,
+            },
+            DebugView::Barycentrics => match rec.barycentrics {
+                Some((b1, b2)) => Color {
+                    r: 1.0 - b1 - b2,
+                    g: b1,
+                    b: b2,
+                },
+                None => gray(0.0),
+            },
+            DebugView::MaterialId => {
+                id_color(rec.material as *const _ as *const () as usize)
+            }
+            DebugView::ObjectId => {
+                id_color(rec.object as *const _ as *const () as usize)
+            }
+            DebugView::HitMask => gray(1.0),
+        }
+    }
+}
+
+impl Integrator for DebugViewer {
+    fn render(&self, scene: &Scene, camera: &dyn Camera, film: &mut Film) {
+        trace_pixels(camera, film, self.samples, |ray| {
+            match scene.world.hit(ray, 0.001, f64::INFINITY) {
+                Some(rec) => self.view.shade(ray, &rec),
+                None => gray(0.0),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::point::Point;
+    use crate::scene::{Hit, Triangle};
+
+    #[test]
+    fn test_barycentrics_weight_the_nearest_vertex() {
+        let triangle = Triangle::new(
+            [
+                Point::origin(),
+                Point {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                Point {
+                    x: 0.0,
+                    y: 1.0,
+                    z: 0.0,
+                },
+            ],
+            Box::new(Lambertian {
+                albedo: Vector3::zero(),
+            }),
+        );
+        let ray = Ray {
+            origin: Point {
+                x: 0.1,
+                y: 0.1,
+                z: 1.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            wavelengths: None,
+        };
+        let rec = triangle.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let color = DebugView::Barycentrics.shade(&ray, &rec);
+
+        assert!((color.r - 0.8).abs() < 1e-9);
+        assert!((color.g - 0.1).abs() < 1e-9);
+        assert!((color.b - 0.1).abs() < 1e-9);
+    }
+}
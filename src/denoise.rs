@@ -0,0 +1,254 @@
+use crate::color::Color;
+use crate::film::Film;
+use crate::util::black;
+
+/// Weights of the B3 spline that each pass of the filter spreads out.
+const KERNEL: [f64; 5] =
+    [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Edge-avoiding À-trous wavelet filtering, after Dammertz et al. (2010).
+/// Each of `iterations` passes blurs with a 5×5 kernel whose taps are
+/// twice as far apart as in the previous pass, so wide areas are smoothed
+/// cheaply. Taps are weighted down the more their colour, normal or depth
+/// differs from the pixel's, by Gaussians of width `color_sigma`,
+/// `normal_sigma` and, relative to the pixel's depth, `depth_sigma`; the
+/// colour width halves with every pass. Raising `color_sigma` makes the
+/// filter stronger. Passes stop once the taps are as far apart as the
+/// film is wide, whatever `iterations` asks for.
+///
+/// The guides are the film's `albedo`, `normal` and `depth` output
+/// variables. Lighting is divided by the albedo before filtering and
+/// multiplied back afterwards, so texture detail is kept. Missing guides
+/// are ignored.
+pub struct Denoiser {
+    pub iterations: u32,
+    pub color_sigma: f64,
+    pub normal_sigma: f64,
+    pub depth_sigma: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            color_sigma: 1.0,
+            normal_sigma: 0.3,
+            depth_sigma: 0.1,
+        }
+    }
+}
+
+/// The values of one guide or image, row by row.
+struct Buffer {
+    width: usize,
+    height: usize,
+    values: Vec<Color>,
+}
+
+impl Buffer {
+    fn from_film(film: &Film, f: impl Fn(u32, u32) -> Color) -> Self {
+        let (width, height) = (film.width as usize, film.height as usize);
+        let mut values = Vec::with_capacity(width * height);
+        for y in 0..film.height {
+            for x in 0..film.width {
+                values.push(f(x, y));
+            }
+        }
+        Buffer {
+            width,
+            height,
+            values,
+        }
+    }
+
+    fn at(&self, x: usize, y: usize) -> Color {
+        self.values[y * self.width + x]
+    }
+}
+
+fn distance_squared(a: &Color, b: &Color) -> f64 {
+    let d = *a - *b;
+    d.r * d.r + d.g * d.g + d.b * d.b
+}
+
+fn map(c: &Color, f: impl Fn(f64, f64) -> f64, other: &Color) -> Color {
+    Color {
+        r: f(c.r, other.r),
+        g: f(c.g, other.g),
+        b: f(c.b, other.b),
+    }
+}
+
+/// Divides lighting by albedo where there is albedo to divide by.
+fn demodulate(c: f64, albedo: f64) -> f64 {
+    if albedo > 1e-3 { c / albedo } else { c }
+}
+
+fn remodulate(c: f64, albedo: f64) -> f64 {
+    if albedo > 1e-3 { c * albedo } else { c }
+}
+
+impl Denoiser {
+    /// A film holding the filtered pixels of `film`.
+    pub fn denoise(&self, film: &Film) -> Film {
+        let guide = |name: &'static str| {
+            Buffer::from_film(film, move |x, y| {
+                film.aov(name, x, y).unwrap_or(black())
+            })
+        };
+        let (albedo, normal, depth) =
+            (guide("albedo"), guide("normal"), guide("depth"));
+        let has_albedo = film.aov_names().any(|name| name == "albedo");
+        let mut image = Buffer::from_film(film, |x, y| {
+            let c = film.pixel(x, y);
+            match film.aov("albedo", x, y) {
+                Some(a) => map(&c, demodulate, &a),
+                None => c,
+            }
+        });
+
+        for i in 0..self.passes(film) {
+            let step = 1 << i;
+            let color_sigma = self.color_sigma / f64::from(step);
+            image = self.pass(
+                &image,
+                &normal,
+                &depth,
+                i64::from(step),
+                color_sigma,
+            );
+        }
+
+        let mut denoised = Film::new(film.width, film.height);
+        for y in 0..film.height {
+            for x in 0..film.width {
+                let (i, j) = (x as usize, y as usize);
+                let c = image.at(i, j);
+                let c = if has_albedo {
+                    map(&c, remodulate, &albedo.at(i, j))
+                } else {
+                    c
+                };
+                denoised.add_sample(x, y, c);
+            }
+        }
+        denoised
+    }
+
+    /// How many of `iterations` to run on `film`: those whose taps are
+    /// nearer together than the film's larger side.
+    fn passes(&self, film: &Film) -> u32 {
+        let size = film.width.max(film.height).max(1);
+        self.iterations.min(u32::BITS - (size - 1).leading_zeros())
+    }
+
+    fn pass(
+        &self,
+        image: &Buffer,
+        normal: &Buffer,
+        depth: &Buffer,
+        step: i64,
+        color_sigma: f64,
+    ) -> Buffer {
+        let (width, height) = (image.width as i64, image.height as i64);
+        let mut values = Vec::with_capacity(image.values.len());
+        for y in 0..height {
+            for x in 0..width {
+                let (px, py) = (x as usize, y as usize);
+                let (c, n) = (image.at(px, py), normal.at(px, py));
+                let z = depth.at(px, py).r;
+
+                let mut sum = black();
+                let mut total = 0.0;
+                for (dy, ky) in (-2..=2).zip(KERNEL) {
+                    for (dx, kx) in (-2..=2).zip(KERNEL) {
+                        let qx = (x + dx * step).clamp(0, width - 1) as usize;
+                        let qy = (y + dy * step).clamp(0, height - 1) as usize;
+                        let q = image.at(qx, qy);
+                        let dz = (depth.at(qx, qy).r - z)
+                            / (self.depth_sigma * z.max(1e-3));
+                        let w = kx
+                            * ky
+                            * (-distance_squared(&c, &q)
+                                / (color_sigma * color_sigma))
+                                .exp()
+                            * (-distance_squared(&n, &normal.at(qx, qy))
+                                / (self.normal_sigma * self.normal_sigma))
+                                .exp()
+                            * (-dz * dz).exp();
+                        sum = sum + w * q;
+                        total += w;
+                    }
+                }
+                values.push(sum / total);
+            }
+        }
+        Buffer {
+            width: image.width,
+            height: image.height,
+            values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::gray;
+
+    #[test]
+    fn test_smooths_noise_on_a_flat_surface() {
+        let mut film = Film::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let noise = if (x + y) % 2 == 0 { 0.1 } else { -0.1 };
+                film.add_sample(x, y, gray(0.5 + noise));
+            }
+        }
+        let denoised = Denoiser::default().denoise(&film);
+
+        let c = denoised.pixel(8, 8);
+        assert!((c.r - 0.5).abs() < 0.02, "{:?}", c);
+    }
+
+    #[test]
+    fn test_keeps_edges_between_normals() {
+        let mut film = Film::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let (c, n) = if x < 8 { (0.6, 1.0) } else { (0.4, -1.0) };
+                film.add_sample(x, y, gray(c));
+                film.add_aov("normal", x, y, gray(n));
+            }
+        }
+        let denoised = Denoiser::default().denoise(&film);
+
+        assert!((denoised.pixel(7, 8).r - 0.6).abs() < 1e-3);
+        assert!((denoised.pixel(8, 8).r - 0.4).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_stops_once_taps_span_the_film() {
+        let mut film = Film::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                film.add_sample(x, y, gray(f64::from((x * 7 + y * 3) % 5)));
+            }
+        }
+        let denoise = |iterations| {
+            Denoiser {
+                iterations,
+                ..Denoiser::default()
+            }
+            .denoise(&film)
+        };
+        let (most, four) = (denoise(u32::MAX), denoise(4));
+
+        assert_ne!(four.pixel(5, 5), denoise(3).pixel(5, 5));
+        for y in 0..16 {
+            for x in 0..16 {
+                assert_eq!(most.pixel(x, y), four.pixel(x, y));
+            }
+        }
+    }
+}
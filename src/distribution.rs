@@ -0,0 +1,170 @@
+/// A piecewise-constant distribution over [0, 1) with one bucket per value
+/// of the function it was built from.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    /// Builds a distribution proportional to `func`, which must not be
+    /// empty. Negative values are treated as zero, and a function that is
+    /// zero everywhere gives a uniform distribution.
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let func: Vec<f64> = func.into_iter().map(|f| f.max(0.0)).collect();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.func.is_empty()
+    }
+
+    /// The integral of the function over [0, 1).
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Maps a uniform `u` to a point in [0, 1), returning the point, its
+    /// density and the bucket it fell in.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let offset = self.sample_discrete(u);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 {
+            (u - self.cdf[offset]) / width
+        } else {
+            0.0
+        };
+        let x = ((offset as f64 + du) / self.len() as f64).min(1.0 - 1e-12);
+        (x, self.pdf(x), offset)
+    }
+
+    /// Maps a uniform `u` to a bucket, chosen proportionally to its value.
+    pub fn sample_discrete(&self, u: f64) -> usize {
+        let i = self.cdf.partition_point(|c| *c <= u);
+        i.saturating_sub(1).min(self.len() - 1)
+    }
+
+    /// The probability of picking bucket `i` with `sample_discrete`.
+    pub fn discrete_pdf(&self, i: usize) -> f64 {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+
+    /// The density of `sample_continuous` at `x`.
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.len() as f64) as usize).min(self.len() - 1);
+        self.discrete_pdf(i) * self.len() as f64
+    }
+}
+
+/// A piecewise-constant distribution over the unit square, sampled by first
+/// picking `v` from the marginal distribution of rows and then `u` within
+/// the chosen row.
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Builds a distribution from `width * height` values stored row by row.
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(
+            conditional.iter().map(|d| d.integral()).collect(),
+        );
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Maps two uniform numbers to a point `(u, v)` and its density.
+    pub fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (v, _, row) = self.marginal.sample_continuous(u2);
+        let (u, _, _) = self.conditional[row].sample_continuous(u1);
+        ((u, v), self.pdf(u, v))
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let height = self.conditional.len();
+        let row = ((v * height as f64) as usize).min(height - 1);
+        self.marginal.pdf(v) * self.conditional[row].pdf(u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_sample_follows_function() {
+        let d = Distribution1D::new(vec![1.0, 3.0]);
+
+        assert_close(d.integral(), 2.0);
+        assert_close(d.discrete_pdf(0), 0.25);
+        assert_close(d.discrete_pdf(1), 0.75);
+        assert_eq!(d.sample_discrete(0.2), 0);
+        assert_eq!(d.sample_discrete(0.3), 1);
+
+        let (x, pdf, offset) = d.sample_continuous(0.625);
+        assert_close(x, 0.75);
+        assert_close(pdf, 1.5);
+        assert_eq!(offset, 1);
+    }
+
+    #[test]
+    fn test_zero_function_is_uniform() {
+        let d = Distribution1D::new(vec![0.0; 4]);
+
+        assert_close(d.pdf(0.1), 1.0);
+        assert_eq!(d.sample_discrete(0.6), 2);
+    }
+
+    #[test]
+    fn test_2d_pdf_integrates_to_one() {
+        let func = [1.0, 2.0, 0.0, 5.0, 1.0, 1.0];
+        let d = Distribution2D::new(&func, 3, 2);
+        let mut integral = 0.0;
+        for y in 0..2 {
+            for x in 0..3 {
+                integral +=
+                    d.pdf((x as f64 + 0.5) / 3.0, (y as f64 + 0.5) / 2.0) / 6.0;
+            }
+        }
+
+        assert_close(integral, 1.0);
+        let ((u, v), pdf) = d.sample(0.5, 0.9);
+        assert!(v >= 0.5);
+        assert_close(pdf, d.pdf(u, v));
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+
+use image::{Rgb32FImage, RgbImage};
+
+use crate::color::Color;
+use crate::spectrum::{Wavelengths, xyz_to_rgb};
+use crate::util::black;
+
+/// Accumulates radiance samples per pixel and turns their averages into an
+/// image. Pixel rows are stored bottom to top, matching the camera's `t`
+/// coordinate.
+///
+/// Splats are contributions that land on a pixel other than the one being
+/// sampled, as light tracing produces. They are summed rather than averaged
+/// and divided by the mean number of samples per pixel, or kept as they are
+/// on a film with no samples at all.
+///
+/// Output variables are further named buffers, such as albedo or depth,
+/// that integrators fill alongside the radiance. They are averaged over
+/// the radiance samples of each pixel.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    sums: Vec<Color>,
+    counts: Vec<u32>,
+    total: u64,
+    splats: Vec<Color>,
+    aovs: BTreeMap<String, Vec<Color>>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        let size = (width * height) as usize;
+        Film {
+            width,
+            height,
+            sums: vec![black(); size],
+            counts: vec![0; size],
+            total: 0,
+            splats: vec![black(); size],
+            aovs: BTreeMap::new(),
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    /// Adds a linear RGB radiance sample to pixel `(x, y)`.
+    pub fn add_sample(&mut self, x: u32, y: u32, color: Color) {
+        let i = self.index(x, y);
+        self.sums[i] = self.sums[i] + color;
+        self.counts[i] += 1;
+        self.total += 1;
+    }
+
+    /// Adds a sample traced at `wavelengths`, whose channels hold radiance
+    /// per wavelength, by way of its XYZ colour.
+    pub fn add_spectral_sample(
+        &mut self,
+        x: u32,
+        y: u32,
+        radiance: Color,
+        wavelengths: &Wavelengths,
+    ) {
+        self.add_sample(x, y, xyz_to_rgb(&wavelengths.to_xyz(&radiance)));
+    }
+
+    /// Adds a splat at film position `(s, t)`, each running from zero to
+    /// one.
+    pub fn add_splat(&mut self, s: f64, t: f64, color: Color) {
+        let x = ((s * f64::from(self.width)) as u32).min(self.width - 1);
+        let y = ((t * f64::from(self.height)) as u32).min(self.height - 1);
+        let i = self.index(x, y);
+        self.splats[i] = self.splats[i] + color;
+    }
+
+    pub fn add_spectral_splat(
+        &mut self,
+        s: f64,
+        t: f64,
+        radiance: Color,
+        wavelengths: &Wavelengths,
+    ) {
+        self.add_splat(s, t, xyz_to_rgb(&wavelengths.to_xyz(&radiance)));
+    }
+
+    /// Adds `value` to pixel `(x, y)` of the output variable `name`,
+    /// creating its buffer the first time.
+    pub fn add_aov(&mut self, name: &str, x: u32, y: u32, value: Color) {
+        let i = self.index(x, y);
+        let size = self.sums.len();
+        let buffer = self
+            .aovs
+            .entry(name.to_string())
+            .or_insert_with(|| vec![black(); size]);
+        buffer[i] = buffer[i] + value;
+    }
+
+    /// The names of the output variables recorded so far, in order.
+    pub fn aov_names(&self) -> impl Iterator<Item = &str> {
+        self.aovs.keys().map(String::as_str)
+    }
+
+    /// Pixel `(x, y)` of the output variable `name`, if it was recorded.
+    pub fn aov(&self, name: &str, x: u32, y: u32) -> Option<Color> {
+        let i = self.index(x, y);
+        let sum = self.aovs.get(name)?[i];
+        Some(sum / f64::from(self.counts[i].max(1)))
+    }
+
+    /// The output variable `name` as linear floats, top row first, for
+    /// saving as OpenEXR.
+    pub fn aov_image(&self, name: &str) -> Option<Rgb32FImage> {
+        self.aovs.get(name)?;
+        Some(Rgb32FImage::from_fn(self.width, self.height, |x, y| {
+            let col = self.aov(name, x, self.height - 1 - y).unwrap();
+            image::Rgb([col.r as f32, col.g as f32, col.b as f32])
+        }))
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = self.index(x, y);
+        let average = if self.counts[i] == 0 {
+            self.sums[i]
+        } else {
+            self.sums[i] / f64::from(self.counts[i])
+        };
+        if self.total == 0 {
+            return average + self.splats[i];
+        }
+        average
+            + self.splats[i] * (self.counts.len() as f64 / self.total as f64)
+    }
+
+    /// The averaged pixels with a gamma of two, top row first.
+    pub fn to_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let col = self.pixel(x, self.height - 1 - y);
+            image::Rgb([
+                (col.r.max(0.0).sqrt() * 255.0) as u8,
+                (col.g.max(0.0).sqrt() * 255.0) as u8,
+                (col.b.max(0.0).sqrt() * 255.0) as u8,
+            ])
+        })
+    }
+}
@@ -0,0 +1,288 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+/// An error reading an IES file.
+#[derive(Debug)]
+pub enum IesError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for IesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IesError::Io(err) => write!(f, "could not read IES file: {}", err),
+            IesError::Parse(msg) => write!(f, "invalid IES file: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for IesError {}
+
+impl From<std::io::Error> for IesError {
+    fn from(err: std::io::Error) -> Self {
+        IesError::Io(err)
+    }
+}
+
+/// The measured candela distribution of a light fixture, read from an IES
+/// LM-63 photometric file using type C photometry. Vertical angles run from
+/// 0° straight down the fixture's axis to 180° straight up, and horizontal
+/// angles around the axis.
+#[derive(Clone, Debug)]
+pub struct IesProfile {
+    /// Vertical angles in degrees, in increasing order.
+    vertical: Vec<f64>,
+    /// Horizontal angles in degrees, in increasing order.
+    horizontal: Vec<f64>,
+    /// Candela values for each horizontal angle, one per vertical angle.
+    candela: Vec<Vec<f64>>,
+    peak: f64,
+}
+
+struct Tokens<'a> {
+    iter: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Tokens<'a> {
+    fn number(&mut self, what: &str) -> Result<f64, IesError> {
+        let token = self
+            .iter
+            .next()
+            .ok_or_else(|| IesError::Parse(format!("missing {}", what)))?;
+        token
+            .parse()
+            .map_err(|_| IesError::Parse(format!("bad {} {:?}", what, token)))
+    }
+
+    fn count(&mut self, what: &str) -> Result<usize, IesError> {
+        let n = self.number(what)?;
+        if n < 0.0 || n.fract() != 0.0 {
+            return Err(IesError::Parse(format!("bad {} {}", what, n)));
+        }
+        Ok(n as usize)
+    }
+
+    fn list(&mut self, n: usize, what: &str) -> Result<Vec<f64>, IesError> {
+        (0..n).map(|_| self.number(what)).collect()
+    }
+}
+
+impl IesProfile {
+    /// Reads a profile from the text of an IES file.
+    pub fn parse(text: &str) -> Result<Self, IesError> {
+        let tilt = text
+            .find("TILT=")
+            .ok_or_else(|| IesError::Parse("missing TILT line".to_string()))?;
+        let rest = &text[tilt..];
+        let line_end = rest.find('\n').unwrap_or(rest.len());
+        let mut tokens = Tokens {
+            iter: rest[line_end..].split_whitespace(),
+        };
+
+        if rest[..line_end].trim() == "TILT=INCLUDE" {
+            // Lamp to luminaire geometry, then tilt angles and multipliers,
+            // which do not apply to fixtures used in their rated position.
+            tokens.number("lamp geometry")?;
+            let n = tokens.count("number of tilt angles")?;
+            tokens.list(2 * n, "tilt data")?;
+        }
+
+        tokens.number("number of lamps")?;
+        tokens.number("lumens per lamp")?;
+        let multiplier = tokens.number("candela multiplier")?;
+        let nv = tokens.count("number of vertical angles")?;
+        let nh = tokens.count("number of horizontal angles")?;
+        let photometric_type = tokens.number("photometric type")?;
+        if photometric_type != 1.0 {
+            return Err(IesError::Parse(format!(
+                "unsupported photometric type {}",
+                photometric_type
+            )));
+        }
+        // Units, width, length, height, ballast factor, ballast-lamp
+        // photometric factor and input watts.
+        tokens.list(7, "luminaire data")?;
+        if nv == 0 || nh == 0 {
+            return Err(IesError::Parse("no angles".to_string()));
+        }
+
+        let vertical = tokens.list(nv, "vertical angle")?;
+        let horizontal = tokens.list(nh, "horizontal angle")?;
+        let candela = (0..nh)
+            .map(|_| {
+                tokens.list(nv, "candela value").map(|values| {
+                    values.iter().map(|c| c * multiplier).collect()
+                })
+            })
+            .collect::<Result<Vec<Vec<f64>>, IesError>>()?;
+
+        let increasing =
+            |angles: &[f64]| angles.windows(2).all(|pair| pair[0] < pair[1]);
+        if !increasing(&vertical) || !increasing(&horizontal) {
+            return Err(IesError::Parse("angles out of order".to_string()));
+        }
+        let peak = candela
+            .iter()
+            .flat_map(|row| row.iter())
+            .fold(0.0, |max: f64, c| max.max(*c));
+        Ok(IesProfile {
+            vertical,
+            horizontal,
+            candela,
+            peak,
+        })
+    }
+
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, IesError> {
+        IesProfile::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The brightest value in the distribution.
+    pub fn max_candela(&self) -> f64 {
+        self.peak
+    }
+
+    /// The intensity towards the given angles as a fraction of the peak.
+    pub fn relative(&self, vertical: f64, horizontal: f64) -> f64 {
+        if self.peak > 0.0 {
+            self.candela(vertical, horizontal) / self.peak
+        } else {
+            0.0
+        }
+    }
+
+    /// Folds `horizontal` into the range the file covers, following the
+    /// symmetry implied by its last horizontal angle.
+    fn fold_horizontal(&self, horizontal: f64) -> f64 {
+        let h = horizontal.rem_euclid(360.0);
+        let first = self.horizontal[0];
+        match self.horizontal[self.horizontal.len() - 1] - first {
+            span if span <= 0.0 => first,
+            // The same in each quadrant.
+            span if span <= 90.0 => {
+                let h = h % 180.0;
+                first + if h > 90.0 { 180.0 - h } else { h }
+            }
+            // Mirrored about the 0°-180° plane.
+            span if span <= 180.0 => {
+                first + if h > 180.0 { 360.0 - h } else { h }
+            }
+            _ => (h - first).rem_euclid(360.0) + first,
+        }
+    }
+
+    /// The intensity in candela towards the given angles, in degrees,
+    /// interpolated bilinearly between the measured ones.
+    pub fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        let v = &self.vertical;
+        if vertical < v[0] || vertical > v[v.len() - 1] {
+            return 0.0;
+        }
+        let h = self.fold_horizontal(horizontal);
+        let (i0, i1, ti) = bracket(&self.horizontal, h, true);
+        let (j0, j1, tj) = bracket(v, vertical, false);
+        let row = |i: usize| {
+            let row = &self.candela[i];
+            (1.0 - tj) * row[j0] + tj * row[j1]
+        };
+        (1.0 - ti) * row(i0) + ti * row(i1)
+    }
+
+    /// The average intensity over the whole sphere as a fraction of the
+    /// peak, for estimating the power of a light using the profile.
+    pub fn average(&self) -> f64 {
+        let steps = 64;
+        let mut sum = 0.0;
+        for i in 0..steps {
+            let theta = PI * (f64::from(i) + 0.5) / f64::from(steps);
+            for j in 0..2 * steps {
+                let phi = 180.0 * (f64::from(j) + 0.5) / f64::from(steps);
+                sum += self.relative(theta.to_degrees(), phi) * theta.sin();
+            }
+        }
+        let cell = PI / f64::from(steps) * PI / f64::from(steps);
+        sum * cell / (4.0 * PI)
+    }
+}
+
+/// The two entries of `angles` around `x` and how far `x` lies between
+/// them. With `wrap`, angles past the last one interpolate back towards
+/// the first across 360°.
+fn bracket(angles: &[f64], x: f64, wrap: bool) -> (usize, usize, f64) {
+    let n = angles.len();
+    if n == 1 {
+        return (0, 0, 0.0);
+    }
+    let i = angles.partition_point(|a| *a <= x);
+    if i == 0 {
+        return (0, 0, 0.0);
+    }
+    if i == n {
+        if wrap && angles[n - 1] - angles[0] < 360.0 {
+            let span = angles[0] + 360.0 - angles[n - 1];
+            return (n - 1, 0, (x - angles[n - 1]) / span);
+        }
+        return (n - 1, n - 1, 0.0);
+    }
+    let t = (x - angles[i - 1]) / (angles[i] - angles[i - 1]);
+    (i - 1, i, t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] 123
+[MANUFAC] Example
+TILT=NONE
+1 1000 2.0 3 3 1 2 0.1 0.1 0.0
+1.0 1.0 20
+0 45 90
+0 45 90
+100 50 0
+100 40 0
+100 30 0
+";
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_parse_scales_by_multiplier() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+
+        assert_eq!(profile.vertical, vec![0.0, 45.0, 90.0]);
+        assert_eq!(profile.horizontal.len(), 3);
+        assert_close(profile.max_candela(), 200.0);
+        assert_close(profile.candela(45.0, 45.0), 80.0);
+        assert_close(profile.candela(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_interpolates_and_folds_quadrants() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+
+        assert_close(profile.candela(22.5, 0.0), 150.0);
+        assert_close(profile.candela(45.0, 22.5), 90.0);
+        // Quadrant symmetry mirrors 135° onto 45° and 270° onto 90°.
+        assert_close(profile.candela(45.0, 135.0), 80.0);
+        assert_close(profile.candela(45.0, 270.0), 60.0);
+    }
+
+    #[test]
+    fn test_skips_included_tilt() {
+        let text = DOWNLIGHT
+            .replace("TILT=NONE\n", "TILT=INCLUDE\n1\n2\n0 90\n1.0 0.5\n");
+        let profile = IesProfile::parse(&text).unwrap();
+
+        assert_close(profile.candela(0.0, 0.0), 200.0);
+    }
+
+    #[test]
+    fn test_rejects_truncated_file() {
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1.0 3").is_err());
+    }
+}
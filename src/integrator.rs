@@ -0,0 +1,109 @@
+use rand::prelude::*;
+
+use crate::aov::PathAovs;
+use crate::camera::{Camera, CameraRay};
+use crate::color::Color;
+use crate::film::Film;
+use crate::ray::Ray;
+use crate::sampler::rng;
+use crate::scene::Scene;
+use crate::spectrum::Wavelengths;
+use crate::util::{PathSettings, black, render_ray, render_ray_aovs, weighted};
+use crate::vector::Vector3;
+
+/// A way of turning a scene into an image.
+pub trait Integrator {
+    fn render(&self, scene: &Scene, camera: &dyn Camera, film: &mut Film);
+}
+
+/// Unidirectional path tracing with next event estimation, tracing
+/// `samples` paths through each pixel with `render_ray`. With `aovs` set,
+/// each path's output variables are recorded on the film as well.
+pub struct PathTracer {
+    pub samples: u32,
+    pub spectral: bool,
+    pub settings: PathSettings,
+    pub aovs: bool,
+}
+
+impl PathTracer {
+    fn trace(
+        &self,
+        camera_ray: &CameraRay,
+        scene: &Scene,
+        film: &mut Film,
+        x: u32,
+        y: u32,
+    ) {
+        let ray = &camera_ray.ray;
+        let weight = &camera_ray.weight;
+        let (col, aovs) = if *weight == Vector3::zero() {
+            (black(), PathAovs::default())
+        } else if self.aovs {
+            render_ray_aovs(ray, scene, &self.settings)
+        } else {
+            let col = render_ray(ray, scene, &self.settings);
+            (col, PathAovs::default())
+        };
+        let col = weighted(col, weight);
+        match ray.wavelengths {
+            Some(w) => film.add_spectral_sample(x, y, col, &w),
+            None => film.add_sample(x, y, col),
+        }
+        if !self.aovs {
+            return;
+        }
+        let aovs = aovs.weighted(weight);
+        let aovs = match ray.wavelengths {
+            Some(w) => aovs.to_rgb(&w),
+            None => aovs,
+        };
+        for (name, value) in aovs.layers() {
+            film.add_aov(name, x, y, value);
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn render(&self, scene: &Scene, camera: &dyn Camera, film: &mut Film) {
+        let mut rng = rng();
+        let (nx, ny) = (film.width, film.height);
+        for y in 0..ny {
+            for x in 0..nx {
+                for _ in 0..self.samples {
+                    let u = (f64::from(x) + rng.gen::<f64>()) / f64::from(nx);
+                    let v = (f64::from(y) + rng.gen::<f64>()) / f64::from(ny);
+
+                    let wavelengths = if self.spectral {
+                        Some(Wavelengths::sample(rng.gen()))
+                    } else {
+                        None
+                    };
+                    let ray = camera.generate_ray(u, v, wavelengths);
+                    self.trace(&ray, scene, film, x, y);
+                }
+            }
+        }
+    }
+}
+
+/// Adds to each pixel what `f` returns for `samples` camera rays through
+/// random points in it.
+pub(crate) fn trace_pixels(
+    camera: &dyn Camera,
+    film: &mut Film,
+    samples: u32,
+    mut f: impl FnMut(&Ray) -> Color,
+) {
+    let mut rng = rng();
+    let (nx, ny) = (film.width, film.height);
+    for y in 0..ny {
+        for x in 0..nx {
+            for _ in 0..samples {
+                let u = (f64::from(x) + rng.gen::<f64>()) / f64::from(nx);
+                let v = (f64::from(y) + rng.gen::<f64>()) / f64::from(ny);
+                film.add_sample(x, y, f(&camera.get_ray(u, v)));
+            }
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::fmt;
+
+use crate::material::refract;
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::vector::Vector3;
+
+/// An error reading or focusing a lens prescription.
+#[derive(Debug)]
+pub enum LensError {
+    Io(std::io::Error),
+    Parse(String),
+    /// The lens cannot be used as asked, like focusing closer than it can.
+    Design(String),
+}
+
+impl fmt::Display for LensError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LensError::Io(err) => write!(f, "could not read lens: {}", err),
+            LensError::Parse(msg) => write!(f, "invalid lens: {}", msg),
+            LensError::Design(msg) => write!(f, "unusable lens: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for LensError {}
+
+impl From<std::io::Error> for LensError {
+    fn from(err: std::io::Error) -> Self {
+        LensError::Io(err)
+    }
+}
+
+/// One surface of a lens system, in millimetres.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LensElement {
+    /// Radius of the spherical surface, positive when its centre lies
+    /// towards the film, or zero for the flat aperture stop.
+    pub curvature_radius: f64,
+    /// Distance along the axis to the next surface towards the film, or
+    /// to the film itself for the last one.
+    pub thickness: f64,
+    /// Refractive index of what lies behind the surface, towards the film,
+    /// with zero meaning air.
+    pub ior: f64,
+    pub aperture_radius: f64,
+}
+
+/// The surfaces of a lens system, listed from the scene towards the film.
+///
+/// In lens space the film is the plane z = 0, centred on the optical axis,
+/// and the lens lies along +z in front of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LensPrescription {
+    pub elements: Vec<LensElement>,
+}
+
+/// Height above the axis of the rays that find a lens's focal points.
+const PARAXIAL_HEIGHT: f64 = 0.01;
+
+impl LensPrescription {
+    /// Reads a table with one surface per line: curvature radius,
+    /// thickness, index of refraction and aperture diameter, as in PBRT's
+    /// lens files. Lines starting with `#` are comments.
+    pub fn parse(text: &str) -> Result<Self, LensError> {
+        let mut elements = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|token| token.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|_| {
+                    LensError::Parse(format!(
+                        "bad number on line {}",
+                        number + 1
+                    ))
+                })?;
+            if values.len() != 4 {
+                return Err(LensError::Parse(format!(
+                    "expected 4 values on line {}, found {}",
+                    number + 1,
+                    values.len()
+                )));
+            }
+            elements.push(LensElement {
+                curvature_radius: values[0],
+                thickness: values[1],
+                ior: values[2],
+                aperture_radius: values[3] / 2.0,
+            });
+        }
+        if elements.is_empty() {
+            return Err(LensError::Parse("no surfaces".to_string()));
+        }
+        Ok(LensPrescription { elements })
+    }
+
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, LensError> {
+        LensPrescription::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// A 50 mm f/2 double Gauss lens, from US patent 2,673,491 scaled down
+    /// from 100 mm.
+    pub fn double_gauss() -> Self {
+        let table = [
+            (29.475, 3.76, 1.67, 25.2),
+            (84.83, 0.12, 1.0, 25.2),
+            (19.275, 4.025, 1.67, 23.0),
+            (40.77, 3.275, 1.699, 23.0),
+            (12.75, 5.705, 1.0, 18.0),
+            (0.0, 4.5, 0.0, 17.1),
+            (-14.495, 1.18, 1.603, 17.0),
+            (40.77, 6.065, 1.658, 20.0),
+            (-20.385, 0.19, 1.0, 20.0),
+            (437.065, 3.22, 1.717, 20.0),
+            (-39.73, 0.0, 1.0, 20.0),
+        ];
+        LensPrescription {
+            elements: table
+                .iter()
+                .map(|&(curvature_radius, thickness, ior, diameter)| {
+                    LensElement {
+                        curvature_radius,
+                        thickness,
+                        ior,
+                        aperture_radius: diameter / 2.0,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Narrows the aperture stop to `diameter`, which cannot open it any
+    /// wider than the design allows.
+    pub fn stop_down(&mut self, diameter: f64) -> Result<(), LensError> {
+        let stop = self
+            .elements
+            .iter_mut()
+            .find(|e| e.curvature_radius == 0.0)
+            .ok_or_else(|| LensError::Design("no aperture stop".to_string()))?;
+        if diameter > 2.0 * stop.aperture_radius {
+            return Err(LensError::Design(format!(
+                "the stop opens to at most {} mm",
+                2.0 * stop.aperture_radius
+            )));
+        }
+        stop.aperture_radius = diameter / 2.0;
+        Ok(())
+    }
+
+    /// Distance from the film to the front surface.
+    pub fn front_z(&self) -> f64 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    /// The last surface before the film.
+    pub fn rear(&self) -> &LensElement {
+        self.elements.last().unwrap()
+    }
+
+    /// Follows `ray` through every surface, from the film out into the
+    /// scene or the other way round, giving the ray that leaves the last
+    /// one, or `None` if an element or the stop blocks it.
+    pub fn trace(&self, ray: &Ray, from_film: bool) -> Option<Ray> {
+        let n = self.elements.len();
+        let ior = |i: usize| match self.elements[i].ior {
+            0.0 => 1.0,
+            ior => ior,
+        };
+        let mut ray = Ray {
+            origin: ray.origin,
+            direction: ray.direction.normalize(),
+            wavelengths: ray.wavelengths,
+        };
+        let mut z = if from_film { 0.0 } else { self.front_z() };
+        for step in 0..n {
+            let i = if from_film { n - 1 - step } else { step };
+            let element = &self.elements[i];
+            if from_film {
+                z += element.thickness;
+            } else if i > 0 {
+                z -= self.elements[i - 1].thickness;
+            }
+
+            let (t, normal) = if element.curvature_radius == 0.0 {
+                ((z - ray.origin.z) / ray.direction.z, None)
+            } else {
+                let (t, normal) =
+                    hit_surface(&ray, z, element.curvature_radius)?;
+                (t, Some(normal))
+            };
+            if t.is_nan() || t <= 0.0 {
+                return None;
+            }
+            let p = ray.at(t);
+            if p.x * p.x + p.y * p.y > element.aperture_radius.powi(2) {
+                return None;
+            }
+            ray.origin = p;
+
+            if let Some(normal) = normal {
+                // The glass of element `i` lies on its film side, and that
+                // of the one before it on its scene side.
+                let outside = if i > 0 { ior(i - 1) } else { 1.0 };
+                let (from, to) = if from_film {
+                    (ior(i), outside)
+                } else {
+                    (outside, ior(i))
+                };
+                ray.direction =
+                    refract(ray.direction, normal, from / to)?.normalize();
+            }
+        }
+        Some(ray)
+    }
+
+    /// Moves the lens against the film, by changing the thickness after
+    /// the last surface, to focus on a plane `distance` from the film.
+    ///
+    /// The lens is treated as a thick lens: two principal planes and a
+    /// focal length, found by tracing rays parallel to the axis through it
+    /// from either side.
+    pub fn focus(&mut self, distance: f64) -> Result<(), LensError> {
+        let parallel = |z: f64, dz: f64| Ray {
+            origin: Point {
+                x: PARAXIAL_HEIGHT,
+                y: 0.0,
+                z,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: dz,
+            },
+            wavelengths: None,
+        };
+        let no_focus = || LensError::Design("rays do not focus".to_string());
+
+        let film_side = self
+            .trace(&parallel(self.front_z() + 1.0, -1.0), false)
+            .ok_or_else(no_focus)?;
+        let scene_side = self
+            .trace(&parallel(-1.0, 1.0), true)
+            .ok_or_else(no_focus)?;
+        // Where each ray crosses the axis and where it is back at its
+        // starting height.
+        let crossing = |ray: &Ray, x: f64| {
+            ray.origin.z
+                + (x - ray.origin.x) / ray.direction.x * ray.direction.z
+        };
+        let focal_point = crossing(&film_side, 0.0);
+        let film_principal = crossing(&film_side, PARAXIAL_HEIGHT);
+        let scene_principal = crossing(&scene_side, PARAXIAL_HEIGHT);
+        let focal_length = film_principal - focal_point;
+        if focal_length.is_nan() || focal_length <= 0.0 {
+            return Err(no_focus());
+        }
+
+        // Shifting the lens by `delta` takes the object distance to
+        // a - delta and the image distance to b + delta, which must meet
+        // the lens equation.
+        let a = distance - scene_principal;
+        let b = film_principal;
+        let discriminant = (a + b) * (a + b - 4.0 * focal_length);
+        if discriminant < 0.0 {
+            return Err(LensError::Design(format!(
+                "cannot focus closer than {:.1} mm",
+                4.0 * focal_length + scene_principal - film_principal
+            )));
+        }
+        let delta = 0.5 * (a - b - discriminant.sqrt());
+        let last = self.elements.last_mut().unwrap();
+        if last.thickness + delta <= 0.0 {
+            return Err(LensError::Design(
+                "the lens would have to sit behind the film".to_string(),
+            ));
+        }
+        last.thickness += delta;
+        Ok(())
+    }
+}
+
+/// Where `ray` meets the spherical surface whose vertex is on the axis at
+/// `z`, and the surface normal there facing against the ray.
+fn hit_surface(ray: &Ray, z: f64, radius: f64) -> Option<(f64, Vector3)> {
+    let centre = Point {
+        x: 0.0,
+        y: 0.0,
+        z: z - radius,
+    };
+    let oc = ray.origin - centre;
+    let b = oc.dot(&ray.direction);
+    let c = oc.norm() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    // The surface is the half of the sphere nearest its vertex, which the
+    // ray reaches first exactly when it travels towards the sphere's
+    // centre along the axis.
+    let t = if (ray.direction.z < 0.0) ^ (radius < 0.0) {
+        -b - root
+    } else {
+        -b + root
+    };
+    let normal = (ray.at(t) - centre) / radius.abs();
+    if normal.dot(&ray.direction) > 0.0 {
+        Some((t, -normal))
+    } else {
+        Some((t, normal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reads_diameters_and_skips_comments() {
+        let lens = LensPrescription::parse(
+            "# radius thickness ior aperture\n\
+             35.0 4.0 1.5 20.0\n\
+             \n\
+             0 3 0 12\n",
+        )
+        .unwrap();
+
+        assert_eq!(lens.elements.len(), 2);
+        assert_eq!(lens.elements[0].aperture_radius, 10.0);
+        assert_eq!(lens.elements[1].curvature_radius, 0.0);
+        assert!(LensPrescription::parse("35.0 4.0 1.5\n").is_err());
+    }
+}
@@ -1,8 +1,29 @@
+pub mod aov;
+pub mod aperture;
+pub mod bdpt;
+pub mod bump;
 pub mod camera;
 pub mod color;
+pub mod debug;
+pub mod denoise;
+pub mod distribution;
+pub mod film;
+pub mod ies;
+pub mod integrator;
+pub mod lens;
+pub mod light;
+pub mod light_sampler;
 pub mod material;
+pub mod medium;
+pub mod mlt;
+pub mod onb;
 pub mod point;
 pub mod ray;
+pub mod sampler;
 pub mod scene;
+pub mod sky;
+pub mod spectrum;
+pub mod sppm;
+pub mod texture;
 pub mod util;
 pub mod vector;
@@ -0,0 +1,756 @@
+use std::f64::consts::PI;
+
+use image::Rgb32FImage;
+use rand::prelude::*;
+
+use crate::color::Color;
+use crate::distribution::Distribution2D;
+use crate::ies::IesProfile;
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::sampler::rng;
+use crate::scene::{Bounds, Shape};
+use crate::texture::Texture;
+use crate::util::{black, random_cosine_direction, random_unit_vector};
+use crate::vector::Vector3;
+
+/// Light arriving at a point from a sampled position on a light.
+pub struct LightSample {
+    /// Unit direction from the receiving point towards the light.
+    pub direction: Vector3,
+    /// Distance to the light along `direction`, infinite for lights that
+    /// are infinitely far away.
+    pub distance: f64,
+    pub radiance: Color,
+    /// Solid angle density of `direction`, or one for delta lights.
+    pub pdf: f64,
+}
+
+/// A ray leaving a light, for tracing paths that start at the lights.
+pub struct Emission {
+    pub ray: Ray,
+    /// The surface normal where the ray leaves, or its direction for
+    /// lights without a surface.
+    pub normal: Vector3,
+    pub radiance: Color,
+    /// Area density of the ray's origin, or one for a point.
+    pub pdf_pos: f64,
+    /// Solid angle density of the ray's direction.
+    pub pdf_dir: f64,
+}
+
+pub trait Light {
+    fn sample(&self, p: &Point) -> Option<LightSample>;
+
+    /// Whether the light sits at a single point or shines from a single
+    /// direction, so that only `sample` can ever reach it.
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// The solid angle density with which `sample` would pick `direction`
+    /// from `p`. Delta lights can never be hit by chance and return zero.
+    fn pdf(&self, _p: &Point, _direction: &Vector3) -> f64 {
+        0.0
+    }
+
+    /// The radiance carried by a ray that leaves the scene without hitting
+    /// anything, for lights that surround it.
+    fn le(&self, _ray: &Ray) -> Color {
+        black()
+    }
+
+    /// The shape the light is emitted from, for lights attached to
+    /// geometry.
+    fn shape(&self) -> Option<&dyn Shape> {
+        None
+    }
+
+    /// Where the light is, or `None` for lights infinitely far away.
+    fn bounds(&self) -> Option<Bounds> {
+        None
+    }
+
+    /// An estimate of the luminous power the light emits, used to sample
+    /// bright lights more often than dim ones. Lights without bounds are
+    /// not compared by power and may leave it at zero.
+    fn power(&self) -> f64 {
+        0.0
+    }
+
+    /// Picks a ray leaving the light. Lights infinitely far away cannot
+    /// start paths and return `None`.
+    fn sample_le(&self) -> Option<Emission> {
+        None
+    }
+
+    /// The position and direction densities with which `sample_le` would
+    /// pick a ray leaving `p`, where the light's normal is `normal`, along
+    /// `direction`.
+    fn pdf_le(
+        &self,
+        _p: &Point,
+        _normal: &Vector3,
+        _direction: &Vector3,
+    ) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+}
+
+/// Picks a ray carrying light from `light`, which must be infinitely far
+/// away, into the region `world`. Rays start on a disc as wide as the
+/// region, just outside it on the side the light arrives from.
+pub fn sample_infinite_le(
+    light: &dyn Light,
+    world: &Bounds,
+) -> Option<Emission> {
+    let center = world.center();
+    let radius = 0.5 * world.diagonal().length();
+    let sample = light.sample(&center)?;
+    if radius <= 0.0 || sample.pdf <= 0.0 {
+        return None;
+    }
+    let mut rng = rng();
+    let r = rng.gen::<f64>().sqrt();
+    let phi = 2.0 * PI * rng.gen::<f64>();
+    let frame = Onb::from_w(sample.direction);
+    let offset = frame.local(&Vector3 {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z: 1.0,
+    });
+    Some(Emission {
+        ray: Ray {
+            origin: center + radius * offset,
+            direction: -sample.direction,
+            wavelengths: None,
+        },
+        normal: -sample.direction,
+        radiance: sample.radiance,
+        pdf_pos: 1.0 / (PI * radius * radius),
+        pdf_dir: sample.pdf,
+    })
+}
+
+/// Light emitted by the surface of an object whose material glows, created
+/// for it by `Hit::lights`.
+pub struct AreaLight<'a> {
+    pub shape: &'a dyn Shape,
+    power: f64,
+}
+
+impl<'a> AreaLight<'a> {
+    pub fn new(shape: &'a dyn Shape, material: &dyn Material) -> Self {
+        AreaLight {
+            shape,
+            power: PI * shape.area() * material.average_emission(),
+        }
+    }
+}
+
+impl Light for AreaLight<'_> {
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let sample = self.shape.sample(p)?;
+        let to_light = sample.p - *p;
+        let distance = to_light.length();
+        let ray = Ray {
+            origin: *p,
+            direction: to_light / distance,
+            wavelengths: None,
+        };
+        // The emission, and whether the sampled point is the first one seen
+        // from `p`, come from tracing the shape itself.
+        let rec = self.shape.hit(&ray, 0.001, f64::INFINITY)?;
+        if (rec.t - distance).abs() > 1e-4 * distance.max(1.0) {
+            return None;
+        }
+        Some(LightSample {
+            direction: ray.direction,
+            distance: rec.t,
+            radiance: rec.material.emitted(&ray, &rec),
+            pdf: sample.pdf,
+        })
+    }
+
+    fn pdf(&self, p: &Point, direction: &Vector3) -> f64 {
+        self.shape.pdf(p, direction)
+    }
+
+    fn shape(&self) -> Option<&dyn Shape> {
+        Some(self.shape)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(self.shape.bounds())
+    }
+
+    fn power(&self) -> f64 {
+        self.power
+    }
+
+    /// Leaves a uniformly chosen point in a cosine weighted direction.
+    fn sample_le(&self) -> Option<Emission> {
+        let (p, normal) = self.shape.sample_area();
+        let direction = Onb::from_w(normal).local(&random_cosine_direction());
+        // Looks back at the point to find what the material emits there.
+        let probe = Ray {
+            origin: p + 1e-3 * direction,
+            direction: -direction,
+            wavelengths: None,
+        };
+        let rec = self.shape.hit(&probe, 0.0, 2e-3)?;
+        Some(Emission {
+            ray: Ray {
+                origin: rec.offset_origin(&direction),
+                direction,
+                wavelengths: None,
+            },
+            normal,
+            radiance: rec.material.emitted(&probe, &rec),
+            pdf_pos: 1.0 / self.shape.area(),
+            pdf_dir: direction.dot(&normal).max(0.0) / PI,
+        })
+    }
+
+    fn pdf_le(
+        &self,
+        _p: &Point,
+        normal: &Vector3,
+        direction: &Vector3,
+    ) -> (f64, f64) {
+        (
+            1.0 / self.shape.area(),
+            direction.normalize().dot(normal).max(0.0) / PI,
+        )
+    }
+}
+
+/// Light spreading from a single point. Without a `profile` it spreads
+/// equally in all directions. With one, `intensity` is the peak of the
+/// measured distribution, whose axis points down the -y axis with
+/// horizontal angles measured around it from +x towards +z.
+pub struct PointLight {
+    pub position: Point,
+    pub intensity: Color,
+    pub profile: Option<IesProfile>,
+}
+
+impl PointLight {
+    /// The intensity leaving the light in `direction`.
+    pub fn emission(&self, direction: &Vector3) -> Color {
+        match &self.profile {
+            Some(profile) => {
+                let d = direction.normalize();
+                let vertical = (-d.y).clamp(-1.0, 1.0).acos().to_degrees();
+                let horizontal = d.z.atan2(d.x).to_degrees();
+                profile.relative(vertical, horizontal) * self.intensity
+            }
+            None => self.intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance = to_light.length();
+        if distance <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: self.emission(&-to_light) / (distance * distance),
+            pdf: 1.0,
+        })
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds {
+            min: self.position,
+            max: self.position,
+        })
+    }
+
+    fn power(&self) -> f64 {
+        let average = self.profile.as_ref().map_or(1.0, |p| p.average());
+        4.0 * PI * average * luminance(&self.intensity)
+    }
+
+    fn sample_le(&self) -> Option<Emission> {
+        let direction = random_unit_vector();
+        Some(Emission {
+            ray: Ray {
+                origin: self.position,
+                direction,
+                wavelengths: None,
+            },
+            normal: direction,
+            radiance: self.emission(&direction),
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * PI),
+        })
+    }
+
+    fn pdf_le(
+        &self,
+        _p: &Point,
+        _normal: &Vector3,
+        _direction: &Vector3,
+    ) -> (f64, f64) {
+        (1.0, 1.0 / (4.0 * PI))
+    }
+}
+
+fn smoothstep(a: f64, b: f64, x: f64) -> f64 {
+    if a == b {
+        return if x < a { 0.0 } else { 1.0 };
+    }
+    let t = ((x - a) / (b - a)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// A point light limited to a cone around `direction`. Intensity fades out
+/// smoothly between the falloff and cone angles, and an optional
+/// `texture` is projected through the cone like a gobo, with the cone's
+/// edge at the border of the texture. An optional `profile` shapes the
+/// beam further, with its axis along `direction`.
+pub struct SpotLight {
+    pub position: Point,
+    pub intensity: Color,
+    pub texture: Option<Box<dyn Texture>>,
+    pub profile: Option<IesProfile>,
+    frame: Onb,
+    cos_total: f64,
+    cos_falloff: f64,
+    tan_total: f64,
+}
+
+/// The widest cone a spot light takes, in degrees. A gobo is projected
+/// onto a plane in front of the light, which a cone of 90° or more no
+/// longer fits on.
+const MAX_CONE_ANGLE: f64 = 89.0;
+
+impl SpotLight {
+    /// Angles are in degrees, measured from `direction` to the edge of the
+    /// cone and to where the falloff starts. The cone is narrowed to
+    /// `MAX_CONE_ANGLE` if wider.
+    pub fn new(
+        position: Point,
+        direction: Vector3,
+        intensity: Color,
+        cone_angle: f64,
+        falloff_angle: f64,
+    ) -> Self {
+        let cone_angle = cone_angle.min(MAX_CONE_ANGLE);
+        let total = cone_angle.to_radians();
+        SpotLight {
+            position,
+            intensity,
+            texture: None,
+            profile: None,
+            frame: Onb::from_w(direction.normalize()),
+            cos_total: total.cos(),
+            cos_falloff: falloff_angle.min(cone_angle).to_radians().cos(),
+            tan_total: total.tan(),
+        }
+    }
+
+    /// The intensity leaving the light in `direction`.
+    pub fn emission(&self, direction: &Vector3) -> Color {
+        let local = self.frame.to_local(direction);
+        let falloff = smoothstep(self.cos_total, self.cos_falloff, local.z);
+        if falloff <= 0.0 {
+            return black();
+        }
+        let intensity = match &self.profile {
+            Some(profile) => {
+                let vertical = local.z.clamp(-1.0, 1.0).acos().to_degrees();
+                let horizontal = local.y.atan2(local.x).to_degrees();
+                falloff * profile.relative(vertical, horizontal)
+            }
+            None => falloff,
+        } * self.intensity;
+        match &self.texture {
+            Some(texture) => {
+                let u = 0.5 + 0.5 * local.x / (local.z * self.tan_total);
+                let v = 0.5 + 0.5 * local.y / (local.z * self.tan_total);
+                let tex = texture.value(u, v, &self.position);
+                Color {
+                    r: intensity.r * tex.x,
+                    g: intensity.g * tex.y,
+                    b: intensity.b * tex.z,
+                }
+            }
+            None => intensity,
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance = to_light.length();
+        if distance <= 0.0 {
+            return None;
+        }
+        let direction = to_light / distance;
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.emission(&-direction) / (distance * distance),
+            pdf: 1.0,
+        })
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds {
+            min: self.position,
+            max: self.position,
+        })
+    }
+
+    fn power(&self) -> f64 {
+        let cone = 1.0 - 0.5 * (self.cos_total + self.cos_falloff);
+        let average = self.profile.as_ref().map_or(1.0, |p| p.average());
+        2.0 * PI * cone * average * luminance(&self.intensity)
+    }
+
+    /// Leaves in a direction picked uniformly within the cone.
+    fn sample_le(&self) -> Option<Emission> {
+        let mut rng = rng();
+        let cos_theta = 1.0 - rng.gen::<f64>() * (1.0 - self.cos_total);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let direction = self.frame.local(&Vector3 {
+            x: sin_theta * phi.cos(),
+            y: sin_theta * phi.sin(),
+            z: cos_theta,
+        });
+        Some(Emission {
+            ray: Ray {
+                origin: self.position,
+                direction,
+                wavelengths: None,
+            },
+            normal: direction,
+            radiance: self.emission(&direction),
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (2.0 * PI * (1.0 - self.cos_total)),
+        })
+    }
+
+    fn pdf_le(
+        &self,
+        _p: &Point,
+        _normal: &Vector3,
+        direction: &Vector3,
+    ) -> (f64, f64) {
+        let inside = direction.normalize().dot(&self.frame.w) >= self.cos_total;
+        let pdf_dir = if inside {
+            1.0 / (2.0 * PI * (1.0 - self.cos_total))
+        } else {
+            0.0
+        };
+        (1.0, pdf_dir)
+    }
+}
+
+/// Parallel light from an infinitely distant source such as the sun,
+/// travelling along `direction` and delivering `irradiance` to a surface
+/// facing it.
+pub struct DirectionalLight {
+    pub direction: Vector3,
+    pub irradiance: Color,
+}
+
+impl Light for DirectionalLight {
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, _p: &Point) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction.normalize(),
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+            pdf: 1.0,
+        })
+    }
+}
+
+/// The white to light blue gradient the renderer has always used as its sky,
+/// sampled uniformly over the sphere.
+pub struct GradientSky;
+
+impl Light for GradientSky {
+    fn sample(&self, _p: &Point) -> Option<LightSample> {
+        let direction = random_unit_vector();
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.le(&Ray {
+                origin: Point::origin(),
+                direction,
+                wavelengths: None,
+            }),
+            pdf: 1.0 / (4.0 * PI),
+        })
+    }
+
+    fn pdf(&self, _p: &Point, _direction: &Vector3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn le(&self, ray: &Ray) -> Color {
+        let unit_direction = ray.direction.normalize();
+        let t = 0.5 * unit_direction.y + 1.0;
+        let white = Color {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+        };
+        let light_blue = Color {
+            r: 0.5,
+            g: 0.7,
+            b: 1.0,
+        };
+
+        (1.0 - t) * white + t * light_blue
+    }
+}
+
+/// The direction at `(u, v)` of an unrotated latitude-longitude map.
+pub(crate) fn equirect_direction(u: f64, v: f64) -> Vector3 {
+    let theta = v * PI;
+    let phi = u * 2.0 * PI;
+    Vector3 {
+        x: theta.sin() * phi.cos(),
+        y: theta.cos(),
+        z: theta.sin() * phi.sin(),
+    }
+}
+
+pub fn luminance(c: &Color) -> f64 {
+    0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b
+}
+
+/// Light from an equirectangular (latitude-longitude) HDR image surrounding
+/// the scene, with +y at the top row. Directions are importance sampled by
+/// the luminance of the image so small, bright features such as the sun are
+/// found by shadow rays instead of by chance.
+pub struct EnvironmentMap {
+    image: Rgb32FImage,
+    pub intensity: f64,
+    rotation: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// `rotation` turns the map around the y axis, in degrees, and
+    /// `intensity` scales its radiance.
+    pub fn new(image: Rgb32FImage, rotation: f64, intensity: f64) -> Self {
+        let (width, height) = image.dimensions();
+        let mut func = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            let sin_theta =
+                (PI * (f64::from(y) + 0.5) / f64::from(height)).sin();
+            for x in 0..width {
+                let texel = image.get_pixel(x, y);
+                func.push(
+                    sin_theta
+                        * luminance(&Color {
+                            r: f64::from(texel[0]),
+                            g: f64::from(texel[1]),
+                            b: f64::from(texel[2]),
+                        }),
+                );
+            }
+        }
+        let distribution =
+            Distribution2D::new(&func, width as usize, height as usize);
+        EnvironmentMap {
+            image,
+            intensity,
+            rotation: rotation.to_radians(),
+            distribution,
+        }
+    }
+
+    /// Loads a map from a `.hdr` or `.exr` file.
+    pub fn open<P: AsRef<std::path::Path>>(
+        path: P,
+        rotation: f64,
+        intensity: f64,
+    ) -> image::ImageResult<Self> {
+        Ok(EnvironmentMap::new(
+            image::open(path)?.into_rgb32f(),
+            rotation,
+            intensity,
+        ))
+    }
+
+    fn uv(&self, direction: &Vector3) -> (f64, f64) {
+        let d = direction.normalize();
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let phi = (d.z.atan2(d.x) + self.rotation).rem_euclid(2.0 * PI);
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn direction(&self, u: f64, v: f64) -> Vector3 {
+        equirect_direction(u - self.rotation / (2.0 * PI), v)
+    }
+
+    fn lookup(&self, u: f64, v: f64) -> Color {
+        let (width, height) = self.image.dimensions();
+        let x = ((u * f64::from(width)) as u32).min(width - 1);
+        let y = ((v * f64::from(height)) as u32).min(height - 1);
+        let texel = self.image.get_pixel(x, y);
+        self.intensity
+            * Color {
+                r: f64::from(texel[0]),
+                g: f64::from(texel[1]),
+                b: f64::from(texel[2]),
+            }
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, _p: &Point) -> Option<LightSample> {
+        let mut rng = rng();
+        let ((u, v), map_pdf) = self.distribution.sample(rng.gen(), rng.gen());
+        let sin_theta = (v * PI).sin();
+        if map_pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: self.direction(u, v),
+            distance: f64::INFINITY,
+            radiance: self.lookup(u, v),
+            pdf: map_pdf / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    fn pdf(&self, _p: &Point, direction: &Vector3) -> f64 {
+        let (u, v) = self.uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn le(&self, ray: &Ray) -> Color {
+        let (u, v) = self.uv(&ray.direction);
+        self.lookup(u, v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::gray;
+
+    fn white() -> Color {
+        gray(1.0)
+    }
+
+    fn down() -> Vector3 {
+        Vector3 {
+            x: 0.0,
+            y: -1.0,
+            z: 0.0,
+        }
+    }
+
+    fn below(depth: f64, aside: f64) -> Point {
+        Point {
+            x: aside,
+            y: -depth,
+            z: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_point_light_falls_off_with_distance_squared() {
+        let light = PointLight {
+            position: Point::origin(),
+            intensity: white(),
+            profile: None,
+        };
+        let near = light.sample(&below(1.0, 0.0)).unwrap();
+        let far = light.sample(&below(2.0, 0.0)).unwrap();
+
+        assert_eq!(near.radiance.r, 1.0);
+        assert_eq!(far.radiance.r, 0.25);
+        assert_eq!(far.direction, -down());
+        assert!(light.sample(&Point::origin()).is_none());
+    }
+
+    #[test]
+    fn test_spot_light_cuts_off_outside_its_cone() {
+        let light =
+            SpotLight::new(Point::origin(), down(), white(), 30.0, 20.0);
+        // 10°, 25° and 40° off the axis, one unit down.
+        let inside = light.sample(&below(1.0, 10f64.to_radians().tan()));
+        let fading = light.sample(&below(1.0, 25f64.to_radians().tan()));
+        let outside = light.sample(&below(1.0, 40f64.to_radians().tan()));
+
+        assert!(inside.unwrap().radiance.r > 0.9);
+        let fading = fading.unwrap().radiance.r;
+        assert!(fading > 0.0 && fading < 0.9, "{}", fading);
+        assert_eq!(outside.unwrap().radiance.r, 0.0);
+        assert!(light.sample(&Point::origin()).is_none());
+    }
+
+    #[test]
+    fn test_wide_spot_gobo_stays_finite() {
+        let mut light =
+            SpotLight::new(Point::origin(), down(), white(), 120.0, 120.0);
+        light.texture = Some(Box::new(crate::texture::ConstantTexture {
+            color: Vector3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+        }));
+        let edge = light.emission(&Vector3 {
+            x: 1.0,
+            y: -0.05,
+            z: 0.0,
+        });
+
+        assert!(edge.r.is_finite());
+        assert!(light.tan_total > 0.0);
+    }
+
+    #[test]
+    fn test_lights_without_area_are_delta() {
+        let point = PointLight {
+            position: Point::origin(),
+            intensity: white(),
+            profile: None,
+        };
+        let spot = SpotLight::new(Point::origin(), down(), white(), 30.0, 20.0);
+        let sun = DirectionalLight {
+            direction: down(),
+            irradiance: white(),
+        };
+
+        assert!(point.is_delta() && spot.is_delta() && sun.is_delta());
+        assert!(!GradientSky.is_delta());
+        let sunlight = sun.sample(&Point::origin()).unwrap();
+        assert_eq!(sunlight.direction, -down());
+        assert_eq!(sunlight.distance, f64::INFINITY);
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use crate::distribution::Distribution1D;
+use crate::light::Light;
+use crate::point::Point;
+use crate::scene::{Bounds, Hit};
+
+/// How a scene picks the light to sample at each shading point.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LightSampling {
+    /// Every light equally often.
+    Uniform,
+    /// Lights in proportion to their power, wherever the shading point is.
+    Power,
+    /// Lights in proportion to their power over their squared distance,
+    /// estimated by walking a hierarchy of lights.
+    Tree,
+}
+
+/// A node of a `LightTree`. Leaves hold the index of a light in the
+/// scene, and interior nodes the indices of their two children.
+struct Node {
+    bounds: Bounds,
+    power: f64,
+    parent: Option<usize>,
+    children: Option<(usize, usize)>,
+    light: Option<usize>,
+}
+
+/// A bounding volume hierarchy over lights, after Conty and Kulla (2018)
+/// but without their orientation cones. At each node the child to descend
+/// into is picked by its power over the squared distance to its bounds, so
+/// nearby and bright groups of lights are sampled most.
+struct LightTree {
+    nodes: Vec<Node>,
+    /// The leaf holding each light, by light index.
+    leaves: Vec<Option<usize>>,
+}
+
+impl LightTree {
+    fn new(lights: &[(usize, Bounds, f64)], count: usize) -> Self {
+        let mut tree = LightTree {
+            nodes: Vec::new(),
+            leaves: vec![None; count],
+        };
+        let mut lights = lights.to_vec();
+        tree.build(&mut lights, None);
+        tree
+    }
+
+    fn build(
+        &mut self,
+        lights: &mut [(usize, Bounds, f64)],
+        parent: Option<usize>,
+    ) -> usize {
+        let index = self.nodes.len();
+        let bounds = lights[1..]
+            .iter()
+            .fold(lights[0].1, |b, light| b.union(&light.1));
+        self.nodes.push(Node {
+            bounds,
+            power: lights.iter().map(|light| light.2).sum(),
+            parent,
+            children: None,
+            light: None,
+        });
+        if lights.len() == 1 {
+            self.nodes[index].light = Some(lights[0].0);
+            self.leaves[lights[0].0] = Some(index);
+            return index;
+        }
+
+        // Split at the median of the light centres along the longest axis.
+        let extent = bounds.diagonal();
+        let axis = |b: &Bounds| {
+            let c = b.center();
+            if extent.x >= extent.y && extent.x >= extent.z {
+                c.x
+            } else if extent.y >= extent.z {
+                c.y
+            } else {
+                c.z
+            }
+        };
+        lights.sort_by(|a, b| axis(&a.1).total_cmp(&axis(&b.1)));
+        let (left, right) = lights.split_at_mut(lights.len() / 2);
+        let left = self.build(left, Some(index));
+        let right = self.build(right, Some(index));
+        self.nodes[index].children = Some((left, right));
+        index
+    }
+
+    fn importance(&self, p: &Point, node: usize) -> f64 {
+        let node = &self.nodes[node];
+        let half = 0.5 * node.bounds.diagonal();
+        let distance2 = (node.bounds.center() - *p).norm().max(half.norm());
+        if distance2 > 0.0 {
+            node.power / distance2
+        } else {
+            node.power
+        }
+    }
+
+    /// The probability of descending from `node` into its first child.
+    fn first_probability(&self, p: &Point, node: usize) -> f64 {
+        let (left, right) = self.nodes[node].children.unwrap();
+        let (a, b) = (self.importance(p, left), self.importance(p, right));
+        if a + b > 0.0 { a / (a + b) } else { 0.5 }
+    }
+
+    fn sample(&self, p: &Point, u: f64) -> (usize, f64) {
+        let mut u = u;
+        let mut node = 0;
+        let mut pmf = 1.0;
+        while let Some((left, right)) = self.nodes[node].children {
+            let first = self.first_probability(p, node);
+            if u < first {
+                u /= first;
+                pmf *= first;
+                node = left;
+            } else {
+                u = ((u - first) / (1.0 - first)).min(1.0 - 1e-12);
+                pmf *= 1.0 - first;
+                node = right;
+            }
+        }
+        (self.nodes[node].light.unwrap(), pmf)
+    }
+
+    fn pmf(&self, p: &Point, light: usize) -> f64 {
+        let mut node = match self.leaves[light] {
+            Some(node) => node,
+            None => return 0.0,
+        };
+        let mut pmf = 1.0;
+        while let Some(parent) = self.nodes[node].parent {
+            let first = self.first_probability(p, parent);
+            pmf *= if self.nodes[parent].children.unwrap().0 == node {
+                first
+            } else {
+                1.0 - first
+            };
+            node = parent;
+        }
+        pmf
+    }
+}
+
+enum Selector {
+    Uniform,
+    Power(Distribution1D),
+    Tree(LightTree),
+}
+
+/// Picks which of a scene's lights to sample. Lights without bounds, such
+/// as skies, each get as much of a chance as all the other lights put
+/// together, since their power cannot be compared with that of the rest.
+pub struct LightSampler {
+    count: usize,
+    infinite: Vec<usize>,
+    finite: Vec<usize>,
+    /// The position in `finite` of each light, by light index.
+    slots: Vec<Option<usize>>,
+    selector: Selector,
+    /// The index of the light attached to each emissive object, keyed by
+    /// the object's address.
+    objects: HashMap<usize, usize>,
+}
+
+impl LightSampler {
+    pub fn new(
+        lights: &[Box<dyn Light + '_>],
+        strategy: LightSampling,
+    ) -> Self {
+        let mut infinite = Vec::new();
+        let mut finite = Vec::new();
+        let mut slots = vec![None; lights.len()];
+        let mut objects = HashMap::new();
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) => {
+                    slots[i] = Some(finite.len());
+                    finite.push((i, bounds, light.power()));
+                }
+                None => infinite.push(i),
+            }
+            if let Some(shape) = light.shape() {
+                objects.insert(shape as *const _ as *const () as usize, i);
+            }
+        }
+
+        let selector = match strategy {
+            _ if finite.is_empty() => Selector::Uniform,
+            LightSampling::Uniform => Selector::Uniform,
+            LightSampling::Power => Selector::Power(Distribution1D::new(
+                finite.iter().map(|light| light.2).collect(),
+            )),
+            LightSampling::Tree => {
+                Selector::Tree(LightTree::new(&finite, lights.len()))
+            }
+        };
+        LightSampler {
+            count: lights.len(),
+            infinite,
+            finite: finite.iter().map(|light| light.0).collect(),
+            slots,
+            selector,
+            objects,
+        }
+    }
+
+    /// The share of samples given to each light without bounds, and to all
+    /// the other lights together.
+    fn group_probability(&self) -> f64 {
+        1.0 / (self.infinite.len() + 1) as f64
+    }
+
+    /// Maps a uniform `u` to a light to sample from `p`, returning its index
+    /// and the probability of picking it.
+    pub fn sample(&self, p: &Point, u: f64) -> Option<(usize, f64)> {
+        if self.count == 0 {
+            return None;
+        }
+        if let Selector::Uniform = self.selector {
+            let index = ((u * self.count as f64) as usize).min(self.count - 1);
+            return Some((index, 1.0 / self.count as f64));
+        }
+
+        let group = self.group_probability();
+        let slot = ((u / group) as usize).min(self.infinite.len());
+        if slot < self.infinite.len() {
+            return Some((self.infinite[slot], group));
+        }
+        let u = ((u - slot as f64 * group) / group).clamp(0.0, 1.0 - 1e-12);
+        let (index, pmf) = match &self.selector {
+            Selector::Tree(tree) => tree.sample(p, u),
+            Selector::Power(power) => {
+                let i = power.sample_discrete(u);
+                (self.finite[i], power.discrete_pdf(i))
+            }
+            Selector::Uniform => unreachable!(),
+        };
+        Some((index, group * pmf))
+    }
+
+    /// The probability that `sample` picks light `index` from `p`.
+    pub fn pmf(&self, p: &Point, index: usize) -> f64 {
+        let group = self.group_probability();
+        match &self.selector {
+            Selector::Uniform => 1.0 / self.count as f64,
+            _ if self.infinite.contains(&index) => group,
+            Selector::Tree(tree) => group * tree.pmf(p, index),
+            Selector::Power(power) => match self.slots[index] {
+                Some(i) => group * power.discrete_pdf(i),
+                None => 0.0,
+            },
+        }
+    }
+
+    /// The indices of the lights without bounds, the only ones a ray that
+    /// leaves the scene can reach.
+    pub fn infinite_lights(&self) -> &[usize] {
+        &self.infinite
+    }
+
+    /// The index of the light attached to `object`, if it is emissive.
+    pub fn light_for(&self, object: &dyn Hit) -> Option<usize> {
+        self.objects
+            .get(&(object as *const _ as *const () as usize))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::light::{GradientSky, PointLight};
+
+    fn lights() -> Vec<Box<dyn Light>> {
+        let mut lights: Vec<Box<dyn Light>> = vec![Box::new(GradientSky)];
+        for i in 0..7 {
+            lights.push(Box::new(PointLight {
+                position: Point {
+                    x: f64::from(i),
+                    y: f64::from(i * i) * 0.1,
+                    z: 0.0,
+                },
+                intensity: Color {
+                    r: 1.0,
+                    g: f64::from(i),
+                    b: 1.0,
+                },
+                profile: None,
+            }));
+        }
+        lights
+    }
+
+    #[test]
+    fn test_pmf_sums_to_one() {
+        let lights = lights();
+        let p = Point {
+            x: 2.5,
+            y: 1.0,
+            z: -1.0,
+        };
+        for strategy in &[
+            LightSampling::Uniform,
+            LightSampling::Power,
+            LightSampling::Tree,
+        ] {
+            let sampler = LightSampler::new(&lights, *strategy);
+            let sum: f64 = (0..lights.len()).map(|i| sampler.pmf(&p, i)).sum();
+
+            assert!((sum - 1.0).abs() < 1e-9, "{:?}: {}", strategy, sum);
+        }
+    }
+
+    #[test]
+    fn test_sample_reports_pmf() {
+        let lights = lights();
+        let p = Point {
+            x: 5.0,
+            y: 0.0,
+            z: 0.5,
+        };
+        for strategy in &[LightSampling::Power, LightSampling::Tree] {
+            let sampler = LightSampler::new(&lights, *strategy);
+            for k in 0..50 {
+                let u = (f64::from(k) + 0.5) / 50.0;
+                let (index, pmf) = sampler.sample(&p, u).unwrap();
+
+                assert!((pmf - sampler.pmf(&p, index)).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tree_prefers_nearby_lights() {
+        let lights = lights();
+        let sampler = LightSampler::new(&lights, LightSampling::Tree);
+        let near = Point {
+            x: 1.0,
+            y: 0.1,
+            z: 0.1,
+        };
+
+        assert!(sampler.pmf(&near, 2) > sampler.pmf(&near, 7));
+    }
+}
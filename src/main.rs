@@ -1,20 +1,39 @@
-use image::ImageBuffer;
+use image::{GenericImage, RgbImage};
 use rand::prelude::*;
 
-use rt::camera::Camera;
-use rt::color::Color;
+use rt::aperture::{Aperture, ApertureImage};
+use rt::bdpt::Bdpt;
+use rt::camera::{
+    Camera, CubemapCamera, EquirectangularCamera, FisheyeCamera,
+    FisheyeMapping, OrthographicCamera, PerspectiveCamera, RealisticCamera,
+};
+use rt::debug::{AmbientOcclusion, DebugView, DebugViewer};
+use rt::denoise::Denoiser;
+use rt::film::Film;
+use rt::integrator::{Integrator, PathTracer};
+use rt::lens::LensPrescription;
+use rt::light::{EnvironmentMap, GradientSky};
+use rt::light_sampler::LightSampling;
 use rt::material::{Dialectric, Lambertian, Metal};
+use rt::mlt::{Estimator, Mlt};
 use rt::point::Point;
-use rt::scene::{Hit, HitList, Sphere};
-use rt::util::render_ray;
+use rt::scene::{Hit, HitList, Scene, Sphere};
+use rt::sky::PreethamSky;
+use rt::sppm::Sppm;
+use rt::util::PathSettings;
 use rt::vector::Vector3;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let projection = arg_value(&args, "--projection");
     let nx = 1500;
     let ny = 750;
-    let num_samples = 5;
-
-    let mut rng = thread_rng();
+    // A cube map strip holds six square faces.
+    let ny = if projection == Some("cubemap") {
+        nx / 6
+    } else {
+        ny
+    };
 
     let camera_origin = Point {
         x: 13.0,
@@ -33,43 +52,319 @@ fn main() {
     };
     let dist_to_focus = 10.0;
     let aperature = 0.1;
-    let camera = Camera::new(
-        camera_origin,
-        look_at,
-        v_up,
-        30.0,
-        f64::from(nx) / f64::from(ny),
-        aperature,
-        dist_to_focus,
-    );
+    let aspect_ratio = f64::from(nx) / f64::from(ny);
+
+    let camera: Box<dyn Camera> = match projection {
+        Some("perspective") | None => {
+            let mut camera = PerspectiveCamera::new(
+                camera_origin,
+                look_at,
+                v_up,
+                30.0,
+                aspect_ratio,
+                arg_value(&args, "--aperture-size")
+                    .map_or(aperature, |a| a.parse().expect("invalid size")),
+                dist_to_focus,
+            );
+            if let Some(path) = arg_value(&args, "--aperture-image") {
+                camera.aperture = Aperture::Image(
+                    ApertureImage::open(path)
+                        .expect("could not load aperture image"),
+                );
+            } else if let Some(blades) = arg_value(&args, "--blades") {
+                camera.aperture = Aperture::Polygon {
+                    blades: blades.parse().expect("invalid blade count"),
+                    rotation: arg_value(&args, "--blade-rotation")
+                        .map_or(0.0, |r: &str| {
+                            r.parse::<f64>().expect("invalid rotation")
+                        })
+                        .to_radians(),
+                };
+            }
+            camera.cat_eye = arg_value(&args, "--cat-eye")
+                .map_or(0.0, |c| c.parse().expect("invalid cat's eye"));
+            camera.chromatic_aberration =
+                arg_value(&args, "--chromatic-aberration").map_or(0.0, |c| {
+                    c.parse().expect("invalid chromatic aberration")
+                });
+            Box::new(camera)
+        }
+        Some("orthographic") => {
+            let width = arg_value(&args, "--view-width")
+                .map_or(8.0, |w| w.parse().expect("invalid view width"));
+            Box::new(OrthographicCamera::new(
+                camera_origin,
+                look_at,
+                v_up,
+                width,
+                width / aspect_ratio,
+            ))
+        }
+        Some(mapping @ ("equidistant" | "equisolid")) => {
+            Box::new(FisheyeCamera::new(
+                camera_origin,
+                look_at,
+                v_up,
+                arg_value(&args, "--fov")
+                    .map_or(180.0, |f| f.parse().expect("invalid fov")),
+                aspect_ratio,
+                if mapping == "equidistant" {
+                    FisheyeMapping::Equidistant
+                } else {
+                    FisheyeMapping::Equisolid
+                },
+            ))
+        }
+        Some("equirectangular") => {
+            Box::new(EquirectangularCamera::new(camera_origin, look_at, v_up))
+        }
+        Some("cubemap") => Box::new(CubemapCamera {
+            origin: camera_origin,
+        }),
+        Some("realistic") => {
+            let mut lens = arg_value(&args, "--lens").map_or_else(
+                LensPrescription::double_gauss,
+                |path| {
+                    LensPrescription::open(path).expect("could not load lens")
+                },
+            );
+            if let Some(diameter) = arg_value(&args, "--lens-stop") {
+                lens.stop_down(diameter.parse().expect("invalid stop"))
+                    .expect("could not stop down the lens");
+            }
+            Box::new(
+                RealisticCamera::new(
+                    camera_origin,
+                    look_at,
+                    v_up,
+                    lens,
+                    arg_value(&args, "--film-diagonal").map_or(35.0, |d| {
+                        d.parse().expect("invalid film diagonal")
+                    }),
+                    aspect_ratio,
+                    dist_to_focus,
+                )
+                .expect("could not focus the lens"),
+            )
+        }
+        Some(other) => panic!("unknown projection {}", other),
+    };
+    let spectral = args.iter().any(|arg| arg == "--spectral");
+    let denoise = args.iter().any(|arg| arg == "--denoise");
+    // The denoiser is guided by the path tracer's output variables.
+    let aovs = denoise || args.iter().any(|arg| arg == "--aovs");
+    if aovs && !matches!(arg_value(&args, "--integrator"), Some("path") | None)
+    {
+        panic!("--aovs and --denoise only work with the path tracer");
+    }
 
     let world = random_scene();
+    let mut scene = Scene::new(world.as_ref());
+    match arg_value(&args, "--environment") {
+        Some(path) => {
+            let rotation = arg_value(&args, "--environment-rotation")
+                .map_or(0.0, |r| r.parse().expect("invalid rotation"));
+            let intensity = arg_value(&args, "--environment-intensity")
+                .map_or(1.0, |i| i.parse().expect("invalid intensity"));
+            let map = EnvironmentMap::open(path, rotation, intensity)
+                .expect("could not load environment map");
+            scene.lights.push(Box::new(map));
+        }
+        None if args.iter().any(|arg| arg == "--sky") => {
+            let elevation: f64 = arg_value(&args, "--sun-elevation")
+                .map_or(30.0, |e| e.parse().expect("invalid sun elevation"));
+            let azimuth: f64 = arg_value(&args, "--sun-azimuth")
+                .map_or(60.0, |a| a.parse().expect("invalid sun azimuth"));
+            let turbidity = arg_value(&args, "--turbidity")
+                .map_or(3.0, |t| t.parse().expect("invalid turbidity"));
+            let (elevation, azimuth) =
+                (elevation.to_radians(), azimuth.to_radians());
+            let sky = PreethamSky::new(
+                Vector3 {
+                    x: elevation.cos() * azimuth.cos(),
+                    y: elevation.sin(),
+                    z: elevation.cos() * azimuth.sin(),
+                },
+                turbidity,
+                Vector3 {
+                    x: 0.3,
+                    y: 0.3,
+                    z: 0.3,
+                },
+                0.05,
+            );
+            scene.lights.push(Box::new(sky.sun()));
+            scene.lights.push(Box::new(sky));
+        }
+        None => scene.lights.push(Box::new(GradientSky)),
+    }
+
+    scene.light_sampling = match arg_value(&args, "--light-sampling") {
+        Some("uniform") => LightSampling::Uniform,
+        Some("power") => LightSampling::Power,
+        Some("tree") | None => LightSampling::Tree,
+        Some(other) => panic!("unknown light sampling {}", other),
+    };
 
-    let img = ImageBuffer::from_fn(nx, ny, |x, y| {
-        let mut col = Color {
-            r: 0.0,
-            g: 0.0,
-            b: 0.0,
-        };
-        for _ in 0..num_samples {
-            let u = (f64::from(x) + rng.gen::<f64>()) / f64::from(nx);
-            let v = (f64::from(y) + rng.gen::<f64>()) / f64::from(ny);
-
-            let ray = camera.get_ray(u, v);
-            col = col + render_ray(&ray, world.as_ref(), 0);
+    let defaults = PathSettings::default();
+    let depth = |name: &str, default: usize| {
+        arg_value(&args, name)
+            .map_or(default, |d| d.parse().expect("invalid depth"))
+    };
+    let settings = PathSettings {
+        max_diffuse: depth("--max-diffuse", defaults.max_diffuse),
+        max_specular: depth("--max-specular", defaults.max_specular),
+        max_transmission: depth(
+            "--max-transmission",
+            defaults.max_transmission,
+        ),
+        roulette_depth: depth("--roulette-depth", defaults.roulette_depth),
+    };
+
+    let samples = arg_value(&args, "--samples")
+        .map_or(5, |n| n.parse().expect("invalid sample count"));
+    let integrator: Box<dyn Integrator> = match arg_value(&args, "--integrator")
+    {
+        Some("path") | None => Box::new(PathTracer {
+            samples,
+            spectral,
+            settings,
+            aovs,
+        }),
+        Some("bdpt") => Box::new(Bdpt {
+            samples,
+            max_depth: arg_value(&args, "--max-depth")
+                .map_or(8, |d| d.parse().expect("invalid depth")),
+            spectral,
+        }),
+        Some("sppm") => Box::new(Sppm {
+            iterations: arg_value(&args, "--iterations")
+                .map_or(16, |n| n.parse().expect("invalid iterations")),
+            photons: arg_value(&args, "--photons")
+                .map_or((nx * ny) as usize, |n| {
+                    n.parse().expect("invalid photon count")
+                }),
+            initial_radius: arg_value(&args, "--radius")
+                .map_or(0.1, |r| r.parse().expect("invalid radius")),
+            max_depth: arg_value(&args, "--max-depth")
+                .map_or(8, |d| d.parse().expect("invalid depth")),
+            spectral,
+        }),
+        Some("mlt") => Box::new(Mlt {
+            estimator: match arg_value(&args, "--estimator") {
+                Some("path") | None => Estimator::Path(settings),
+                Some("bdpt") => Estimator::Bidirectional {
+                    max_depth: arg_value(&args, "--max-depth")
+                        .map_or(8, |d| d.parse().expect("invalid depth")),
+                },
+                Some(other) => panic!("unknown estimator {}", other),
+            },
+            bootstrap: arg_value(&args, "--bootstrap")
+                .map_or(100_000, |n| n.parse().expect("invalid bootstrap")),
+            chains: arg_value(&args, "--chains")
+                .map_or(1000, |n| n.parse().expect("invalid chain count")),
+            mutations_per_pixel: samples as usize,
+            large_step_probability: arg_value(&args, "--large-step")
+                .map_or(0.3, |p| p.parse().expect("invalid probability")),
+            sigma: arg_value(&args, "--sigma")
+                .map_or(0.01, |s| s.parse().expect("invalid sigma")),
+            spectral,
+        }),
+        Some("ao") => Box::new(AmbientOcclusion {
+            samples,
+            rays: arg_value(&args, "--ao-rays")
+                .map_or(16, |n| n.parse().expect("invalid ray count")),
+            radius: arg_value(&args, "--ao-radius")
+                .map_or(1.0, |r| r.parse().expect("invalid radius")),
+        }),
+        Some("debug") => Box::new(DebugViewer {
+            samples,
+            view: match arg_value(&args, "--view") {
+                Some("normal") | None => DebugView::ShadingNormal,
+                Some("geometric-normal") => DebugView::GeometricNormal,
+                Some("depth") => DebugView::Depth {
+                    far: arg_value(&args, "--far")
+                        .map_or(20.0, |f| f.parse().expect("invalid depth")),
+                },
+                Some("uv") => DebugView::Uv,
+                Some("barycentrics") => DebugView::Barycentrics,
+                Some("material") => DebugView::MaterialId,
+                Some("object") => DebugView::ObjectId,
+                Some("mask") => DebugView::HitMask,
+                Some(other) => panic!("unknown view {}", other),
+            },
+        }),
+        Some(other) => panic!("unknown integrator {}", other),
+    };
+
+    // Renders through `camera`, writing any output variables with
+    // `suffix` added to their file names.
+    let render = |camera: &dyn Camera, suffix: &str| {
+        let mut film = Film::new(nx, ny);
+        integrator.render(&scene, camera, &mut film);
+        for name in film.aov_names() {
+            let image = film.aov_image(name).unwrap();
+            image.save(format!("out1{}.{}.exr", suffix, name)).unwrap();
         }
+        if denoise {
+            let defaults = Denoiser::default();
+            let denoiser = Denoiser {
+                iterations: arg_value(&args, "--denoise-iterations")
+                    .map_or(defaults.iterations, |n| {
+                        n.parse().expect("invalid iterations")
+                    }),
+                color_sigma: arg_value(&args, "--denoise-strength")
+                    .map_or(defaults.color_sigma, |s| {
+                        s.parse().expect("invalid strength")
+                    }),
+                ..defaults
+            };
+            film = denoiser.denoise(&film);
+        }
+        film.to_image()
+    };
 
-        col = col / f64::from(num_samples);
-        image::Rgb([
-            (col.r.sqrt() * 255.0) as u8,
-            (col.g.sqrt() * 255.0) as u8,
-            (col.b.sqrt() * 255.0) as u8,
-        ])
+    let layout = match arg_value(&args, "--stereo") {
+        Some(layout) => layout,
+        None => {
+            render(camera.as_ref(), "").save("out1.png").unwrap();
+            return;
+        }
+    };
+    let interocular = arg_value(&args, "--interocular")
+        .map_or(0.064, |d| d.parse().expect("invalid distance"));
+    let convergence = arg_value(&args, "--convergence")
+        .map_or(dist_to_focus, |d| d.parse().expect("invalid distance"));
+    let [left, right] = [-0.5, 0.5].map(|side| {
+        camera
+            .eye(side * interocular, convergence)
+            .expect("projection has no stereo form")
     });
+    let (left, right) = (
+        render(left.as_ref(), "_left"),
+        render(right.as_ref(), "_right"),
+    );
+    let (right_x, right_y) = match layout {
+        "side-by-side" => (nx, 0),
+        "top-bottom" => (0, ny),
+        "files" => {
+            left.save("out1_left.png").unwrap();
+            right.save("out1_right.png").unwrap();
+            return;
+        }
+        other => panic!("unknown stereo layout {}", other),
+    };
+    let mut pair = RgbImage::new(nx + right_x, ny + right_y);
+    pair.copy_from(&left, 0, 0).unwrap();
+    pair.copy_from(&right, right_x, right_y).unwrap();
+    pair.save("out1.png").unwrap();
+}
 
-    image::imageops::flip_vertical(&img)
-        .save("out1.png")
-        .unwrap();
+/// The value following `name` on the command line, if it was given.
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.windows(2)
+        .find(|pair| pair[0] == name)
+        .map(|pair| pair[1].as_str())
 }
 
 fn random_scene() -> Box<dyn Hit> {
@@ -128,13 +423,18 @@ fn random_scene() -> Box<dyn Hit> {
                                 z: 0.5 * (1.0 + rng.gen::<f64>()),
                             },
                             fuzz: 0.5 * rng.gen::<f64>(),
+                            film: None,
                         }),
                     ));
                 } else {
                     hitlist.push(Sphere::new(
                         center,
                         0.2,
-                        Box::new(Dialectric { ref_idx: 1.5 }),
+                        Box::new(Dialectric {
+                            ref_idx: 1.5,
+                            dispersion: None,
+                            film: None,
+                        }),
                     ));
                 }
             }
@@ -148,7 +448,11 @@ fn random_scene() -> Box<dyn Hit> {
             z: 0.0,
         },
         1.0,
-        Box::new(Dialectric { ref_idx: 1.5 }),
+        Box::new(Dialectric {
+            ref_idx: 1.5,
+            dispersion: None,
+            film: None,
+        }),
     ));
 
     hitlist.push(Sphere::new(
@@ -181,6 +485,7 @@ fn random_scene() -> Box<dyn Hit> {
                 z: 0.5,
             },
             fuzz: 0.0,
+            film: None,
         }),
     ));
 
@@ -1,17 +1,91 @@
+use std::f64::consts::PI;
+
 use rand::prelude::*;
 
+use crate::color::Color;
+use crate::light::luminance;
+use crate::medium::{
+    HenyeyGreenstein, pass_weight, sample_distance, scatter_weight,
+};
+use crate::onb::Onb;
+use crate::point::Point;
 use crate::ray::Ray;
-use crate::scene::HitRecord;
-use crate::util::random_in_unit_sphere;
+use crate::sampler::rng;
+use crate::scene::{Hit, HitRecord};
+use crate::texture::Texture;
+use crate::util::{black, random_cosine_direction, random_in_unit_sphere};
 use crate::vector::Vector3;
 
+/// The kind of interaction a scattered ray was drawn from. Only `Diffuse`
+/// lobes have a density that `Material::pdf` can report; the others are
+/// treated as perfectly specular.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Lobe {
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
+impl Lobe {
+    pub fn is_specular(self) -> bool {
+        self != Lobe::Diffuse
+    }
+}
+
 pub struct Scatter {
     pub scattered: Ray,
     pub attenuation: Vector3,
+    pub lobe: Lobe,
 }
 
 pub trait Material {
     fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter>;
+
+    /// The BSDF times the cosine term for light arriving along `direction`
+    /// and leaving back along `ray`. Specular materials have nothing to
+    /// evaluate and return zero.
+    fn eval(
+        &self,
+        _ray: &Ray,
+        _rec: &HitRecord,
+        _direction: &Vector3,
+    ) -> Vector3 {
+        Vector3::zero()
+    }
+
+    /// The solid angle density with which `scatter` picks `direction`.
+    fn pdf(&self, _ray: &Ray, _rec: &HitRecord, _direction: &Vector3) -> f64 {
+        0.0
+    }
+
+    /// The RGB radiance the surface emits back along `ray`.
+    fn emitted(&self, _ray: &Ray, _rec: &HitRecord) -> Color {
+        black()
+    }
+
+    /// Whether `emitted` can ever be non-zero, in which case shapes made of
+    /// the material are added to a scene's lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Roughly the average luminance `emitted` gives on the front of the
+    /// surface, used to weigh emissive objects against each other.
+    fn average_emission(&self) -> f64 {
+        0.0
+    }
+}
+
+fn cosine_pdf(rec: &HitRecord, direction: &Vector3) -> f64 {
+    (direction.normalize().dot(&rec.normal) / PI).max(0.0)
+}
+
+fn cosine_scatter(ray: &Ray, rec: &HitRecord) -> Ray {
+    Ray {
+        origin: rec.p,
+        direction: Onb::from_w(rec.normal).local(&random_cosine_direction()),
+        ..*ray
+    }
 }
 
 pub struct Lambertian {
@@ -19,16 +93,76 @@ pub struct Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
-        let t = rec.p + rec.normal + random_in_unit_sphere();
+    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
         Some(Scatter {
-            scattered: Ray {
-                origin: rec.p,
-                direction: t - rec.p,
-            },
-            attenuation: self.albedo,
+            scattered: cosine_scatter(ray, rec),
+            attenuation: ray.upsample(self.albedo),
+            lobe: Lobe::Diffuse,
         })
     }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> Vector3 {
+        cosine_pdf(rec, direction) * ray.upsample(self.albedo)
+    }
+
+    fn pdf(&self, _ray: &Ray, rec: &HitRecord, direction: &Vector3) -> f64 {
+        cosine_pdf(rec, direction)
+    }
+}
+
+/// Rough diffuse reflection for surfaces like clay and concrete, where
+/// `sigma` is the standard deviation of the microfacet slope angle in
+/// radians. A `sigma` of zero is the same as `Lambertian`.
+pub struct OrenNayar {
+    pub albedo: Vector3,
+    pub sigma: f64,
+}
+
+impl OrenNayar {
+    /// The ratio of this BRDF to a Lambertian one with the same albedo.
+    fn factor(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> f64 {
+        let sigma2 = self.sigma * self.sigma;
+        let a = 1.0 - sigma2 / (2.0 * (sigma2 + 0.33));
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+        let onb = Onb::from_w(rec.normal);
+        let wo = onb.to_local(&-ray.direction.normalize());
+        let wi = onb.to_local(&direction.normalize());
+        let sin_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+        let sin_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        if sin_o < 1e-4 || sin_i < 1e-4 {
+            return a;
+        }
+
+        let cos_phi = ((wo.x * wi.x + wo.y * wi.y) / (sin_o * sin_i)).max(0.0);
+        let (sin_alpha, tan_beta) = if wi.z.abs() > wo.z.abs() {
+            (sin_o, sin_i / wi.z.abs())
+        } else {
+            (sin_i, sin_o / wo.z.abs())
+        };
+        a + b * cos_phi * sin_alpha * tan_beta
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        let scattered = cosine_scatter(ray, rec);
+        let factor = self.factor(ray, rec, &scattered.direction);
+        Some(Scatter {
+            scattered,
+            attenuation: factor * ray.upsample(self.albedo),
+            lobe: Lobe::Diffuse,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> Vector3 {
+        (cosine_pdf(rec, direction) * self.factor(ray, rec, direction))
+            * ray.upsample(self.albedo)
+    }
+
+    fn pdf(&self, _ray: &Ray, rec: &HitRecord, direction: &Vector3) -> f64 {
+        cosine_pdf(rec, direction)
+    }
 }
 
 fn reflect(v: Vector3, n: Vector3) -> Vector3 {
@@ -38,6 +172,7 @@ fn reflect(v: Vector3, n: Vector3) -> Vector3 {
 pub struct Metal {
     pub albedo: Vector3,
     pub fuzz: f64,
+    pub film: Option<ThinFilm>,
 }
 
 impl Material for Metal {
@@ -47,11 +182,21 @@ impl Material for Metal {
             origin: rec.p,
             direction: reflected
                 + Vector3::from(self.fuzz * random_in_unit_sphere()),
+            ..*ray
         };
         if scattered.direction.dot(&rec.normal) > 0.0 {
+            let albedo = ray.upsample(self.albedo);
+            let attenuation = match &self.film {
+                Some(film) => {
+                    let cosine = -ray.direction.normalize().dot(&rec.normal);
+                    film.metal_reflectance(ray, rec, cosine.max(0.0), &albedo)
+                }
+                None => albedo,
+            };
             Some(Scatter {
                 scattered,
-                attenuation: self.albedo,
+                attenuation,
+                lobe: Lobe::Specular,
             })
         } else {
             None
@@ -59,7 +204,11 @@ impl Material for Metal {
     }
 }
 
-fn refract(v: Vector3, n: Vector3, ni_over_nt: f64) -> Option<Vector3> {
+pub(crate) fn refract(
+    v: Vector3,
+    n: Vector3,
+    ni_over_nt: f64,
+) -> Option<Vector3> {
     let v = v.normalize();
     let dt = v.dot(&n);
     let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
@@ -76,50 +225,834 @@ fn schlick(cosine: f64, ref_idx: f64) -> f64 {
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
+/// How the index of refraction of a `Dialectric` varies with wavelength.
+/// Both forms take the wavelength in micrometres.
+#[derive(Copy, Clone, Debug)]
+pub enum Dispersion {
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7 crown glass.
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+        c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+    };
+
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [4.3356, 0.3306, 0.0],
+        c: [0.011_236, 0.030_625, 0.0],
+    };
+
+    /// The index of refraction at `lambda` nanometres.
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let l = lambda / 1000.0;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => (1.0
+                + b.iter()
+                    .zip(c.iter())
+                    .map(|(b, c)| b * l2 / (l2 - c))
+                    .sum::<f64>())
+            .sqrt(),
+        }
+    }
+}
+
+/// A clear refractive material. `ref_idx` is used when rendering in RGB;
+/// when rendering spectrally a `dispersion` curve, if given, takes over and
+/// splits light into its wavelengths.
 pub struct Dialectric {
     pub ref_idx: f64,
+    pub dispersion: Option<Dispersion>,
+    pub film: Option<ThinFilm>,
 }
 
 impl Material for Dialectric {
     fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        let (ref_idx, wavelengths) = match (self.dispersion, ray.wavelengths) {
+            (Some(dispersion), Some(w)) => {
+                (dispersion.ior(w.hero()), Some(w.terminate_secondary()))
+            }
+            _ => (self.ref_idx, None),
+        };
         let (outward_normal, ni_over_nt, cosine) =
             if ray.direction.dot(&rec.normal) > 0.0 {
                 (
                     -rec.normal,
-                    self.ref_idx,
-                    self.ref_idx * ray.direction.dot(&rec.normal)
+                    ref_idx,
+                    ref_idx * ray.direction.dot(&rec.normal)
                         / ray.direction.length(),
                 )
             } else {
                 (
                     rec.normal,
-                    1.0 / self.ref_idx,
+                    1.0 / ref_idx,
                     -ray.direction.dot(&rec.normal) / ray.direction.length(),
                 )
             };
 
-        let reflect_prob = schlick(cosine, self.ref_idx);
-        let scattered = match (
-            refract(ray.direction, outward_normal, ni_over_nt),
-            rand::thread_rng().gen::<f64>() < reflect_prob,
-        ) {
-            (Some(refracted), false) => Ray {
-                origin: rec.p,
-                direction: refracted,
-            },
-            (_, _) => Ray {
-                origin: rec.p,
-                direction: reflect(ray.direction, rec.normal),
-            },
+        let refracted = refract(ray.direction, outward_normal, ni_over_nt);
+        let (reflectance, reflect_prob) = match (&self.film, refracted) {
+            (Some(film), Some(_)) => {
+                let cos_i = (ray.direction.dot(&rec.normal)
+                    / ray.direction.length())
+                .abs();
+                let (outer, inner) = if ni_over_nt < 1.0 {
+                    (1.0, ref_idx)
+                } else {
+                    (ref_idx, 1.0)
+                };
+                let r =
+                    film.dielectric_reflectance(ray, rec, cos_i, outer, inner);
+                (r, (r.x + r.y + r.z) / 3.0)
+            }
+            _ => {
+                let p = schlick(cosine, ref_idx);
+                (Vector3 { x: p, y: p, z: p }, p)
+            }
+        };
+
+        let white = Vector3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
         };
+        let (mut scattered, lobe, attenuation) =
+            match (refracted, rng().gen::<f64>() < reflect_prob) {
+                (Some(refracted), false) => (
+                    Ray {
+                        origin: rec.p,
+                        direction: refracted,
+                        ..*ray
+                    },
+                    Lobe::Transmission,
+                    (white - reflectance) / (1.0 - reflect_prob),
+                ),
+                (refracted, _) => (
+                    Ray {
+                        origin: rec.p,
+                        direction: reflect(ray.direction, rec.normal),
+                        ..*ray
+                    },
+                    Lobe::Specular,
+                    if refracted.is_some() {
+                        reflectance / reflect_prob
+                    } else {
+                        white
+                    },
+                ),
+            };
 
+        let attenuation = match wavelengths {
+            Some((w, weight)) => {
+                scattered.wavelengths = Some(w);
+                attenuation.hadamard(&weight)
+            }
+            None => attenuation,
+        };
         Some(Scatter {
             scattered,
-            attenuation: Vector3 {
-                x: 1.0,
-                y: 1.0,
-                z: 1.0,
+            attenuation,
+            lobe,
+        })
+    }
+}
+
+/// A thin transparent layer on top of a `Dialectric` or `Metal`, such as a
+/// soap film, an oil slick or a lens coating. Light reflected off the top
+/// and bottom of the film interferes, so the reflectance changes with
+/// wavelength and angle. Both `thickness`, in nanometres, and `ior` are read
+/// from the red channel of their textures.
+pub struct ThinFilm {
+    pub thickness: Box<dyn Texture>,
+    pub ior: Box<dyn Texture>,
+}
+
+/// The cosine of the refracted angle going from index `n1` into `n2`, or
+/// `None` on total internal reflection.
+fn cos_transmitted(n1: f64, n2: f64, cos1: f64) -> Option<f64> {
+    let sin2 = (n1 / n2) * (n1 / n2) * (1.0 - cos1 * cos1).max(0.0);
+    if sin2 >= 1.0 {
+        None
+    } else {
+        Some((1.0 - sin2).sqrt())
+    }
+}
+
+/// The s and p polarised Fresnel amplitude coefficients.
+fn fresnel_amplitudes(n1: f64, cos1: f64, n2: f64, cos2: f64) -> (f64, f64) {
+    (
+        (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2),
+        (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2),
+    )
+}
+
+/// The Airy reflectance of a film whose interfaces reflect with amplitudes
+/// `r12` and `r23`, when the light crossing it is delayed by `phase`.
+fn airy(r12: f64, r23: f64, phase: f64) -> f64 {
+    let interference = 2.0 * r12 * r23 * phase.cos();
+    ((r12 * r12 + r23 * r23 + interference)
+        / (1.0 + r12 * r12 * r23 * r23 + interference))
+        .clamp(0.0, 1.0)
+}
+
+impl ThinFilm {
+    /// The reflectance for light arriving through a medium of index `n1` at
+    /// `cos1`, where `r23` gives the amplitudes at the bottom of the film
+    /// for the cosine of the angle inside it.
+    fn reflectance<F>(
+        &self,
+        rec: &HitRecord,
+        n1: f64,
+        cos1: f64,
+        lambda: f64,
+        r23: F,
+    ) -> f64
+    where
+        F: Fn(f64, f64) -> (f64, f64),
+    {
+        let thickness = self.thickness.value(rec.u, rec.v, &rec.p).x;
+        let n2 = self.ior.value(rec.u, rec.v, &rec.p).x;
+        let cos2 = match cos_transmitted(n1, n2, cos1) {
+            Some(cos2) => cos2,
+            None => return 1.0,
+        };
+        let (s12, p12) = fresnel_amplitudes(n1, cos1, n2, cos2);
+        let (s23, p23) = r23(n2, cos2);
+        let phase = 4.0 * std::f64::consts::PI * n2 * thickness * cos2 / lambda;
+        0.5 * (airy(s12, s23, phase) + airy(p12, p23, phase))
+    }
+
+    /// The reflectance over a dielectric of index `n3`, seen from a medium of
+    /// index `n1`, at each of the ray's channel wavelengths.
+    pub fn dielectric_reflectance(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord,
+        cos1: f64,
+        n1: f64,
+        n3: f64,
+    ) -> Vector3 {
+        let lambda = ray.channel_wavelengths();
+        let r = |lambda| {
+            self.reflectance(rec, n1, cos1, lambda, |n2, cos2| {
+                match cos_transmitted(n2, n3, cos2) {
+                    Some(cos3) => fresnel_amplitudes(n2, cos2, n3, cos3),
+                    None => (1.0, 1.0),
+                }
+            })
+        };
+        Vector3 {
+            x: r(lambda[0]),
+            y: r(lambda[1]),
+            z: r(lambda[2]),
+        }
+    }
+
+    /// The reflectance over a metal whose own reflectance is `albedo`, at
+    /// each of the ray's channel wavelengths.
+    pub fn metal_reflectance(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord,
+        cos1: f64,
+        albedo: &Vector3,
+    ) -> Vector3 {
+        let lambda = ray.channel_wavelengths();
+        let r = |lambda, albedo: f64| {
+            let r23 = -albedo.clamp(0.0, 1.0).sqrt();
+            self.reflectance(rec, 1.0, cos1, lambda, |_, _| (r23, r23))
+        };
+        Vector3 {
+            x: r(lambda[0], albedo.x),
+            y: r(lambda[1], albedo.y),
+            z: r(lambda[2], albedo.z),
+        }
+    }
+}
+
+/// Picks between two materials at every hit, choosing `second` with the
+/// probability given by the red channel of `weight`.
+pub struct MixMaterial {
+    pub first: Box<dyn Material>,
+    pub second: Box<dyn Material>,
+    pub weight: Box<dyn Texture>,
+}
+
+impl MixMaterial {
+    fn weight(&self, rec: &HitRecord) -> f64 {
+        self.weight.value(rec.u, rec.v, &rec.p).x.clamp(0.0, 1.0)
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        if rng().gen::<f64>() < self.weight(rec) {
+            self.second.scatter(ray, rec)
+        } else {
+            self.first.scatter(ray, rec)
+        }
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> Vector3 {
+        let weight = self.weight(rec);
+        (1.0 - weight) * self.first.eval(ray, rec, direction)
+            + weight * self.second.eval(ray, rec, direction)
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> f64 {
+        let weight = self.weight(rec);
+        (1.0 - weight) * self.first.pdf(ray, rec, direction)
+            + weight * self.second.pdf(ray, rec, direction)
+    }
+
+    fn emitted(&self, ray: &Ray, rec: &HitRecord) -> Color {
+        let weight = self.weight(rec);
+        (1.0 - weight) * self.first.emitted(ray, rec)
+            + weight * self.second.emitted(ray, rec)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.first.is_emissive() || self.second.is_emissive()
+    }
+
+    fn average_emission(&self) -> f64 {
+        self.first
+            .average_emission()
+            .max(self.second.average_emission())
+    }
+}
+
+/// A surface that glows with `emit` on the side its geometric normal faces
+/// and absorbs all light that reaches it.
+pub struct DiffuseLight {
+    pub emit: Box<dyn Texture>,
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray: &Ray, _rec: &HitRecord) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, ray: &Ray, rec: &HitRecord) -> Color {
+        if ray.direction.dot(&rec.geometric_normal) >= 0.0 {
+            return black();
+        }
+        let emit = self.emit.value(rec.u, rec.v, &rec.p);
+        Color {
+            r: emit.x,
+            g: emit.y,
+            b: emit.z,
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    /// Averages the texture over a coarse grid of texture coordinates.
+    fn average_emission(&self) -> f64 {
+        let steps = 4;
+        let mut sum = 0.0;
+        for i in 0..steps {
+            for j in 0..steps {
+                let u = (f64::from(i) + 0.5) / f64::from(steps);
+                let v = (f64::from(j) + 0.5) / f64::from(steps);
+                let emit = self.emit.value(u, v, &Point::origin());
+                sum += luminance(&Color {
+                    r: emit.x,
+                    g: emit.y,
+                    b: emit.z,
+                });
+            }
+        }
+        sum / f64::from(steps * steps)
+    }
+}
+
+/// A clear dielectric layer over `base`, such as varnish over wood. Light
+/// either reflects off the coating, as often as the Fresnel term says, or
+/// passes through it to scatter once off the base and back out, picking up
+/// `tint` each time it crosses the layer. What the coating reflects back
+/// down on the way out is lost rather than bounced again.
+pub struct Coated {
+    pub base: Box<dyn Material>,
+    pub ref_idx: f64,
+    pub tint: Vector3,
+}
+
+impl Material for Coated {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        let direction = ray.direction.normalize();
+        let cosine = -direction.dot(&rec.normal);
+        if cosine <= 0.0 {
+            return self.base.scatter(ray, rec);
+        }
+
+        if rng().gen::<f64>() < schlick(cosine, self.ref_idx) {
+            return Some(Scatter {
+                scattered: Ray {
+                    origin: rec.p,
+                    direction: reflect(direction, rec.normal),
+                    ..*ray
+                },
+                attenuation: Vector3 {
+                    x: 1.0,
+                    y: 1.0,
+                    z: 1.0,
+                },
+                lobe: Lobe::Specular,
+            });
+        }
+
+        // Picking the base with a chance of one minus the entering Fresnel
+        // term leaves only the exiting one and the tint to weight by, so
+        // the sample agrees with `eval` over `pdf`.
+        let s = self.base.scatter(ray, rec)?;
+        let cos_out = s.scattered.direction.normalize().dot(&rec.normal);
+        if cos_out <= 0.0 {
+            return None;
+        }
+        let tint = ray.upsample(self.tint);
+        Some(Scatter {
+            attenuation: (1.0 - schlick(cos_out, self.ref_idx))
+                * s.attenuation.hadamard(&tint).hadamard(&tint),
+            ..s
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> Vector3 {
+        let cos_in = -ray.direction.normalize().dot(&rec.normal);
+        let cos_out = direction.normalize().dot(&rec.normal);
+        if cos_in <= 0.0 {
+            return self.base.eval(ray, rec, direction);
+        }
+        if cos_out <= 0.0 {
+            return Vector3::zero();
+        }
+        let tint = ray.upsample(self.tint);
+        let transmitted = (1.0 - schlick(cos_in, self.ref_idx))
+            * (1.0 - schlick(cos_out, self.ref_idx));
+        transmitted
+            * self
+                .base
+                .eval(ray, rec, direction)
+                .hadamard(&tint)
+                .hadamard(&tint)
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> f64 {
+        let cos_in = -ray.direction.normalize().dot(&rec.normal);
+        if cos_in <= 0.0 {
+            return self.base.pdf(ray, rec, direction);
+        }
+        (1.0 - schlick(cos_in, self.ref_idx))
+            * self.base.pdf(ray, rec, direction)
+    }
+}
+
+const MAX_WALK_STEPS: usize = 256;
+
+/// Light that enters a closed object, scatters around inside it and leaves
+/// somewhere else, as in skin, wax, marble or jade. The walk inside is
+/// traced against the shape that was hit, so the object must be closed and
+/// should not overlap anything else. `albedo` is the single scattering
+/// albedo and `mean_free_path` the average distance between interactions,
+/// both per channel, and `g` is the anisotropy of scattering inside.
+pub struct Subsurface {
+    pub albedo: Vector3,
+    pub mean_free_path: Vector3,
+    pub ref_idx: f64,
+    pub g: f64,
+}
+
+impl Subsurface {
+    /// Walks from `origin` in `direction` until the light leaves the object,
+    /// returning where it left, the direction it left in and the weight
+    /// gathered along the way.
+    fn walk(
+        &self,
+        ray: &Ray,
+        object: &dyn Hit,
+        origin: Point,
+        direction: Vector3,
+    ) -> Option<(Point, Vector3, Vector3)> {
+        let mut rng = rng();
+        let mfp = ray.upsample(self.mean_free_path);
+        let sigma_t = Vector3 {
+            x: 1.0 / mfp.x,
+            y: 1.0 / mfp.y,
+            z: 1.0 / mfp.z,
+        };
+        let sigma_s = ray.upsample(self.albedo).hadamard(&sigma_t);
+        let phase = HenyeyGreenstein { g: self.g };
+
+        let mut walk = Ray {
+            origin,
+            direction: direction.normalize(),
+            ..*ray
+        };
+        let mut weight = Vector3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+        for _ in 0..MAX_WALK_STEPS {
+            let distance = sample_distance(&sigma_t);
+            let exit = match object.hit(&walk, 1e-4, distance) {
+                Some(exit) => exit,
+                None => {
+                    weight = weight.hadamard(&scatter_weight(
+                        &sigma_t, &sigma_s, distance,
+                    ));
+                    walk = Ray {
+                        origin: walk.at(distance),
+                        direction: phase.sample(&walk.direction),
+                        ..walk
+                    };
+                    continue;
+                }
+            };
+
+            weight = weight.hadamard(&pass_weight(&sigma_t, exit.t));
+            let normal = if walk.direction.dot(&exit.normal) > 0.0 {
+                exit.normal
+            } else {
+                -exit.normal
+            };
+            if let Some(out) = refract(walk.direction, -normal, self.ref_idx) {
+                let reflect_prob =
+                    schlick(out.normalize().dot(&normal), self.ref_idx);
+                if rng.gen::<f64>() >= reflect_prob {
+                    return Some((exit.p, out, weight));
+                }
+            }
+            walk = Ray {
+                origin: exit.p,
+                direction: reflect(walk.direction, normal),
+                ..walk
+            };
+        }
+        None
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        let direction = ray.direction.normalize();
+        let (normal, ni_over_nt) = if direction.dot(&rec.normal) > 0.0 {
+            (-rec.normal, self.ref_idx)
+        } else {
+            (rec.normal, 1.0 / self.ref_idx)
+        };
+        let reflected = Ray {
+            origin: rec.p,
+            direction: reflect(direction, normal),
+            ..*ray
+        };
+        let white = Vector3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+
+        let refracted = match refract(direction, normal, ni_over_nt) {
+            Some(refracted) => refracted,
+            None => {
+                return Some(Scatter {
+                    scattered: reflected,
+                    attenuation: white,
+                    lobe: Lobe::Specular,
+                });
+            }
+        };
+        let cosine = -direction.dot(&normal);
+        let reflect_prob = if ni_over_nt < 1.0 {
+            schlick(cosine, self.ref_idx)
+        } else {
+            schlick(refracted.normalize().dot(&-normal), self.ref_idx)
+        };
+        if rng().gen::<f64>() < reflect_prob {
+            return Some(Scatter {
+                scattered: reflected,
+                attenuation: white,
+                lobe: Lobe::Specular,
+            });
+        }
+        if ni_over_nt > 1.0 {
+            return Some(Scatter {
+                scattered: Ray {
+                    origin: rec.p,
+                    direction: refracted,
+                    ..*ray
+                },
+                attenuation: white,
+                lobe: Lobe::Transmission,
+            });
+        }
+
+        let (origin, direction, attenuation) =
+            self.walk(ray, rec.object, rec.p, refracted)?;
+        Some(Scatter {
+            scattered: Ray {
+                origin,
+                direction,
+                ..*ray
             },
+            attenuation,
+            lobe: Lobe::Transmission,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::Point;
+    use crate::scene::{Hit, Sphere};
+    use crate::texture::ConstantTexture;
+
+    fn assert_close(a: f64, b: f64, eps: f64) {
+        assert!((a - b).abs() < eps, "{} != {}", a, b);
+    }
+
+    fn white() -> Vector3 {
+        Vector3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        }
+    }
+
+    fn up() -> Vector3 {
+        Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    }
+
+    fn unit_sphere() -> Sphere {
+        Sphere::new(
+            Point::origin(),
+            1.0,
+            Box::new(Lambertian { albedo: white() }),
+        )
+    }
+
+    /// The top of `sphere`, hit straight from above.
+    fn top_hit(sphere: &Sphere) -> (Ray, HitRecord<'_>) {
+        let ray = Ray {
+            origin: Point {
+                x: 0.0,
+                y: 3.0,
+                z: 0.0,
+            },
+            direction: -up(),
+            wavelengths: None,
+        };
+        let rec = sphere.hit(&ray, 1e-3, f64::INFINITY).unwrap();
+        (ray, rec)
+    }
+
+    /// Directions leaving the top of the unit sphere, from straight up to
+    /// grazing and below.
+    fn outgoing() -> Vec<Vector3> {
+        (0..8)
+            .map(|i| {
+                let angle = f64::from(i) * 0.25;
+                Vector3 {
+                    x: angle.sin(),
+                    y: angle.cos(),
+                    z: 0.3 * angle.sin(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lambertian_eval_is_pdf_times_albedo() {
+        let sphere = unit_sphere();
+        let (ray, rec) = top_hit(&sphere);
+        let albedo = Vector3 {
+            x: 0.8,
+            y: 0.5,
+            z: 0.2,
+        };
+        let lambertian = Lambertian { albedo };
+
+        assert_close(lambertian.pdf(&ray, &rec, &up()), 1.0 / PI, 1e-12);
+        for direction in outgoing() {
+            let pdf = lambertian.pdf(&ray, &rec, &direction);
+            let eval = lambertian.eval(&ray, &rec, &direction);
+
+            assert!((eval - pdf * albedo).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_smooth_oren_nayar_is_lambertian() {
+        let sphere = unit_sphere();
+        let (ray, rec) = top_hit(&sphere);
+        let albedo = Vector3 {
+            x: 0.8,
+            y: 0.5,
+            z: 0.2,
+        };
+        let smooth = OrenNayar { albedo, sigma: 0.0 };
+        let lambertian = Lambertian { albedo };
+        for direction in outgoing() {
+            let (a, b) = (
+                smooth.eval(&ray, &rec, &direction),
+                lambertian.eval(&ray, &rec, &direction),
+            );
+
+            assert!((a - b).length() < 1e-12);
+            assert_close(
+                smooth.pdf(&ray, &rec, &direction),
+                lambertian.pdf(&ray, &rec, &direction),
+                1e-12,
+            );
+        }
+    }
+
+    #[test]
+    fn test_mix_blends_eval_and_pdf() {
+        let sphere = unit_sphere();
+        let (ray, rec) = top_hit(&sphere);
+        let mix = |weight: f64| MixMaterial {
+            first: Box::new(Lambertian { albedo: white() }),
+            second: Box::new(Metal {
+                albedo: white(),
+                fuzz: 0.0,
+                film: None,
+            }),
+            weight: Box::new(ConstantTexture {
+                color: weight * white(),
+            }),
+        };
+
+        assert_close(mix(0.25).pdf(&ray, &rec, &up()), 0.75 / PI, 1e-12);
+        assert_close(mix(0.25).eval(&ray, &rec, &up()).x, 0.75 / PI, 1e-12);
+        // Weights past one are clamped, so the mirror is always chosen.
+        let lobe = mix(2.0).scatter(&ray, &rec).unwrap().lobe;
+        assert_eq!(lobe, Lobe::Specular);
+    }
+
+    #[test]
+    fn test_clear_coat_reflects_what_fresnel_lets_through() {
+        let sphere = unit_sphere();
+        let (ray, rec) = top_hit(&sphere);
+        let coated = Coated {
+            base: Box::new(Lambertian { albedo: white() }),
+            ref_idx: 1.5,
+            tint: white(),
+        };
+        let n = 20000;
+        let (mut reflected, mut specular) = (0.0, 0);
+        for _ in 0..n {
+            if let Some(s) = coated.scatter(&ray, &rec) {
+                reflected += s.attenuation.x;
+                if s.lobe == Lobe::Specular {
+                    specular += 1;
+                }
+            }
+        }
+        let reflected = reflected / f64::from(n);
+        // The coat's reflection plus what the base sends back out through
+        // it, averaged over the base's cosine lobe.
+        let steps = 1000;
+        let escaping: f64 = (0..steps)
+            .map(|i| {
+                let cosine = (f64::from(i) + 0.5) / f64::from(steps);
+                (1.0 - schlick(cosine, 1.5)) * 2.0 * cosine / f64::from(steps)
+            })
+            .sum();
+        let f0 = schlick(1.0, 1.5);
+
+        assert_close(reflected, f0 + (1.0 - f0) * escaping, 0.01);
+        assert_close(f64::from(specular) / f64::from(n), f0, 0.01);
+    }
+
+    #[test]
+    fn test_coated_samples_agree_with_eval_and_pdf() {
+        let sphere = unit_sphere();
+        let (ray, rec) = top_hit(&sphere);
+        let coated = Coated {
+            base: Box::new(Lambertian {
+                albedo: Vector3 {
+                    x: 0.8,
+                    y: 0.5,
+                    z: 0.2,
+                },
+            }),
+            ref_idx: 1.5,
+            tint: Vector3 {
+                x: 0.9,
+                y: 0.9,
+                z: 0.7,
+            },
+        };
+        for _ in 0..100 {
+            let s = match coated.scatter(&ray, &rec) {
+                Some(s) if s.lobe == Lobe::Diffuse => s,
+                _ => continue,
+            };
+            let direction = s.scattered.direction;
+            let pdf = coated.pdf(&ray, &rec, &direction);
+            let expected = coated.eval(&ray, &rec, &direction) / pdf;
+
+            assert!((s.attenuation - expected).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_subsurface_does_not_create_energy() {
+        let sphere = unit_sphere();
+        let (ray, rec) = top_hit(&sphere);
+        let wax = Subsurface {
+            albedo: 0.8 * white(),
+            mean_free_path: Vector3 {
+                x: 0.1,
+                y: 0.2,
+                z: 0.4,
+            },
+            ref_idx: 1.4,
+            g: 0.0,
+        };
+        let n = 4000;
+        let mut total = Vector3::zero();
+        for _ in 0..n {
+            if let Some(s) = wax.scatter(&ray, &rec) {
+                total = total + s.attenuation;
+            }
+        }
+        // Everything reflected off the surface or carried out after the
+        // walk, on average.
+        let mean = total / f64::from(n);
+
+        for channel in [mean.x, mean.y, mean.z] {
+            assert!(channel > 0.0 && channel <= 1.0, "{:?}", mean);
+        }
+    }
+
+    #[test]
+    fn test_bk7_dispersion() {
+        assert_close(Dispersion::BK7.ior(587.6), 1.5168, 1e-4);
+        assert!(Dispersion::BK7.ior(450.0) > Dispersion::BK7.ior(650.0));
+    }
+
+    #[test]
+    fn test_airy_without_film_is_fresnel() {
+        let (s13, p13) = fresnel_amplitudes(1.0, 1.0, 1.5, 1.0);
+        let (s12, _) = fresnel_amplitudes(1.0, 1.0, 1.33, 1.0);
+        let (s23, _) = fresnel_amplitudes(1.33, 1.0, 1.5, 1.0);
+
+        assert_close(airy(s12, s23, 0.0), s13 * s13, 1e-12);
+        assert_close(s13 * s13, p13 * p13, 1e-12);
+        assert_close(s13 * s13, 0.04, 1e-12);
+    }
+}
@@ -0,0 +1,248 @@
+use std::f64::consts::PI;
+
+use rand::prelude::*;
+
+use crate::material::{Lobe, Material, Scatter};
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::sampler::rng;
+use crate::scene::{Bounds, Hit, HitRecord};
+use crate::vector::Vector3;
+
+/// Samples how far light travels before it interacts with a medium whose
+/// extinction coefficient is `sigma_t` in each channel. One channel is
+/// picked at random to draw the distance, and the weights returned by
+/// `scatter_weight` and `pass_weight` account for that choice.
+pub fn sample_distance(sigma_t: &Vector3) -> f64 {
+    let mut rng = rng();
+    let sigma = match rng.gen_range(0, 3) {
+        0 => sigma_t.x,
+        1 => sigma_t.y,
+        _ => sigma_t.z,
+    };
+    -(1.0 - rng.gen::<f64>()).ln() / sigma
+}
+
+pub fn transmittance(sigma_t: &Vector3, distance: f64) -> Vector3 {
+    Vector3 {
+        x: (-sigma_t.x * distance).exp(),
+        y: (-sigma_t.y * distance).exp(),
+        z: (-sigma_t.z * distance).exp(),
+    }
+}
+
+/// The path weight for scattering at `distance`, drawn by
+/// `sample_distance`, in a medium with scattering coefficient `sigma_s`.
+pub fn scatter_weight(
+    sigma_t: &Vector3,
+    sigma_s: &Vector3,
+    distance: f64,
+) -> Vector3 {
+    let tr = transmittance(sigma_t, distance);
+    let pdf = (sigma_t.x * tr.x + sigma_t.y * tr.y + sigma_t.z * tr.z) / 3.0;
+    sigma_s.hadamard(&tr) / pdf
+}
+
+/// The path weight for passing `distance` without interacting, when
+/// `sample_distance` drew something further.
+pub fn pass_weight(sigma_t: &Vector3, distance: f64) -> Vector3 {
+    let tr = transmittance(sigma_t, distance);
+    tr / ((tr.x + tr.y + tr.z) / 3.0)
+}
+
+/// The Henyey-Greenstein phase function, where `g` runs from -1 for back
+/// scattering through 0 for isotropic to 1 for forward scattering.
+#[derive(Copy, Clone, Debug)]
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    /// The density for turning by an angle whose cosine is `cosine`.
+    pub fn p(&self, cosine: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cosine;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    /// Samples a new direction for light travelling along `direction`.
+    pub fn sample(&self, direction: &Vector3) -> Vector3 {
+        let mut rng = rng();
+        let (u1, u2): (f64, f64) = (rng.gen(), rng.gen());
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+            (1.0 + g * g - sq * sq) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        Onb::from_w(direction.normalize()).local(&Vector3 {
+            x: sin_theta * phi.cos(),
+            y: sin_theta * phi.sin(),
+            z: cos_theta,
+        })
+    }
+}
+
+/// The material of a point inside a participating medium. It scatters
+/// according to `phase` and keeps `albedo` of the light at each event.
+pub struct Volume {
+    pub albedo: Vector3,
+    pub phase: HenyeyGreenstein,
+}
+
+impl Material for Volume {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        Some(Scatter {
+            scattered: Ray {
+                origin: rec.p,
+                direction: self.phase.sample(&ray.direction),
+                ..*ray
+            },
+            attenuation: ray.upsample(self.albedo),
+            lobe: Lobe::Diffuse,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3) -> Vector3 {
+        self.pdf(ray, rec, direction) * ray.upsample(self.albedo)
+    }
+
+    fn pdf(&self, ray: &Ray, _rec: &HitRecord, direction: &Vector3) -> f64 {
+        self.phase
+            .p(ray.direction.normalize().dot(&direction.normalize()))
+    }
+}
+
+/// Fills the inside of `boundary` with a homogeneous medium, such as smoke
+/// or fog, that interacts with light `density` times per unit distance.
+pub struct ConstantMedium<T: Hit> {
+    pub boundary: T,
+    pub density: f64,
+    pub material: Volume,
+}
+
+impl<T: Hit> Hit for ConstantMedium<T> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let enter = self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(ray, enter.t + 1e-4, f64::INFINITY)?;
+        let t0 = enter.t.max(t_min).max(0.0);
+        let t1 = exit.t.min(t_max);
+        if t0 >= t1 {
+            return None;
+        }
+
+        let length = ray.direction.length();
+        let sigma_t = Vector3 {
+            x: self.density,
+            y: self.density,
+            z: self.density,
+        };
+        let t = t0 + sample_distance(&sigma_t) / length;
+        if t >= t1 {
+            return None;
+        }
+
+        let normal = -ray.direction / length;
+        let onb = Onb::from_w(normal);
+        Some(HitRecord {
+            t,
+            p: ray.at(t),
+            normal,
+            geometric_normal: normal,
+            u: 0.0,
+            v: 0.0,
+            dpdu: onb.u,
+            dpdv: onb.v,
+            barycentrics: None,
+            material: &self.material,
+            object: self,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Bounds> {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::DiffuseLight;
+    use crate::point::Point;
+    use crate::scene::Sphere;
+    use crate::texture::ConstantTexture;
+
+    #[test]
+    fn test_isotropic_phase_integrates_to_one() {
+        let hg = HenyeyGreenstein { g: 0.0 };
+
+        assert!((hg.p(0.3) * 4.0 * PI - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_phase_integrates_to_one() {
+        let steps = 10000;
+        for g in &[-0.7, 0.3, 0.9] {
+            let hg = HenyeyGreenstein { g: *g };
+            let integral: f64 = (0..steps)
+                .map(|i| {
+                    let cosine =
+                        -1.0 + 2.0 * (f64::from(i) + 0.5) / f64::from(steps);
+                    hg.p(cosine) * 2.0 * PI * 2.0 / f64::from(steps)
+                })
+                .sum();
+
+            assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+        }
+    }
+
+    #[test]
+    fn test_glowing_boundary_is_not_a_light() {
+        let fog = ConstantMedium {
+            boundary: Sphere::new(
+                Point::origin(),
+                1.0,
+                Box::new(DiffuseLight {
+                    emit: Box::new(ConstantTexture {
+                        color: Vector3 {
+                            x: 1.0,
+                            y: 1.0,
+                            z: 1.0,
+                        },
+                    }),
+                }),
+            ),
+            density: 0.5,
+            material: Volume {
+                albedo: Vector3::zero(),
+                phase: HenyeyGreenstein { g: 0.0 },
+            },
+        };
+
+        let ray = Ray {
+            origin: Point {
+                x: 0.0,
+                y: 0.0,
+                z: 3.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            wavelengths: None,
+        };
+
+        // Rays only ever meet the medium, never its boundary, so the glow
+        // must not be sampled as a light either.
+        assert!(fog.lights().is_empty());
+        for _ in 0..100 {
+            if let Some(rec) = fog.hit(&ray, 0.001, f64::INFINITY) {
+                assert!(!rec.material.is_emissive());
+            }
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use rand::prelude::*;
+
+use crate::bdpt::Bdpt;
+use crate::camera::{Camera, CameraRay};
+use crate::color::Color;
+use crate::distribution::Distribution1D;
+use crate::film::Film;
+use crate::integrator::Integrator;
+use crate::light::luminance;
+use crate::sampler::{PrimarySampler, drawing_from, rng};
+use crate::scene::Scene;
+use crate::spectrum::{Wavelengths, xyz_to_rgb};
+use crate::util::{PathSettings, render_ray, weighted};
+
+/// The estimator whose random numbers Metropolis light transport mutates.
+#[derive(Copy, Clone, Debug)]
+pub enum Estimator {
+    Path(PathSettings),
+    Bidirectional { max_depth: usize },
+}
+
+/// Primary sample space Metropolis light transport, after Kelemen et al.
+/// (2002) and pbrt. Every random number a path estimator draws is read
+/// from a point in the unit hypercube, and Markov chains wander that space
+/// favouring points whose paths carry more light, so the few paths that get
+/// through a narrow gap are explored once found.
+///
+/// A bootstrap phase traces `bootstrap` independent samples to estimate the
+/// image's total brightness and to pick the starting points of `chains`
+/// chains, which between them make `mutations_per_pixel` mutations for
+/// each pixel. Each mutation is a large step, redrawing every number, with
+/// probability `large_step_probability`, or otherwise a small step moving
+/// each number by a normal offset of width `sigma`.
+pub struct Mlt {
+    pub estimator: Estimator,
+    pub bootstrap: usize,
+    pub chains: usize,
+    pub mutations_per_pixel: usize,
+    pub large_step_probability: f64,
+    pub sigma: f64,
+    pub spectral: bool,
+}
+
+/// Contributions of one path to the film, as film positions and RGB.
+type Contributions = Vec<(f64, f64, Color)>;
+
+fn brightness(contributions: &Contributions) -> f64 {
+    contributions
+        .iter()
+        .map(|(_, _, color)| luminance(color))
+        .sum::<f64>()
+        .max(0.0)
+}
+
+impl Mlt {
+    /// Runs the estimator on the random numbers of the current primary
+    /// sampler, starting with the film position and wavelengths.
+    fn evaluate(&self, scene: &Scene, camera: &dyn Camera) -> Contributions {
+        let mut rng = rng();
+        let (s, t): (f64, f64) = (rng.gen(), rng.gen());
+        let wavelengths = if self.spectral {
+            Some(Wavelengths::sample(rng.gen()))
+        } else {
+            None
+        };
+        let CameraRay { ray, weight } = camera.generate_ray(s, t, wavelengths);
+        let mut contributions = match self.estimator {
+            Estimator::Path(settings) => {
+                let col = render_ray(&ray, scene, &settings);
+                vec![(s, t, weighted(col, &weight))]
+            }
+            Estimator::Bidirectional { max_depth } => {
+                let bdpt = Bdpt {
+                    samples: 1,
+                    max_depth,
+                    spectral: self.spectral,
+                };
+                let mut splats = Vec::new();
+                let col = bdpt.sample(scene, camera, &ray, &mut splats);
+                splats.push((s, t, weighted(col, &weight)));
+                splats
+            }
+        };
+        if let Some(w) = ray.wavelengths {
+            for (_, _, color) in contributions.iter_mut() {
+                *color = xyz_to_rgb(&w.to_xyz(color));
+            }
+        }
+        contributions
+    }
+
+    fn run(
+        &self,
+        sampler: PrimarySampler,
+        scene: &Scene,
+        camera: &dyn Camera,
+    ) -> (PrimarySampler, Contributions) {
+        drawing_from(sampler, || self.evaluate(scene, camera))
+    }
+
+    fn sampler(&self, seed: u64) -> PrimarySampler {
+        PrimarySampler::new(seed, self.sigma, self.large_step_probability)
+    }
+}
+
+impl Integrator for Mlt {
+    fn render(&self, scene: &Scene, camera: &dyn Camera, film: &mut Film) {
+        let mut rng = rng();
+        let base: u64 = rng.gen::<u64>() >> 1;
+
+        let weights: Vec<f64> = (0..self.bootstrap)
+            .map(|i| {
+                let sampler = self.sampler(base + i as u64);
+                brightness(&self.run(sampler, scene, camera).1)
+            })
+            .collect();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 || self.chains == 0 {
+            return;
+        }
+        let b = total / self.bootstrap as f64;
+        let seeds = Distribution1D::new(weights);
+
+        let pixels = (film.width * film.height) as usize;
+        let total_mutations = self.mutations_per_pixel * pixels;
+        if total_mutations == 0 {
+            return;
+        }
+        // No more chains than mutations, with the remainder of the budget
+        // spread over the first ones.
+        let chains = self.chains.min(total_mutations);
+        let (per_chain, remainder) =
+            (total_mutations / chains, total_mutations % chains);
+        // Each splat is scaled so the chains together give the brightness
+        // measured by the bootstrap.
+        let scale = b * pixels as f64 / total_mutations as f64;
+        for chain in 0..chains {
+            let mutations = per_chain + usize::from(chain < remainder);
+            let seed = base + seeds.sample_discrete(rng.gen()) as u64;
+            let (mut sampler, mut current) =
+                self.run(self.sampler(seed), scene, camera);
+            let mut current_y = brightness(&current);
+
+            for _ in 0..mutations {
+                sampler.start_iteration();
+                let (mutated, proposed) = self.run(sampler, scene, camera);
+                sampler = mutated;
+                let proposed_y = brightness(&proposed);
+                let accept = if current_y > 0.0 {
+                    (proposed_y / current_y).min(1.0)
+                } else {
+                    1.0
+                };
+
+                // Both states are recorded, weighted by their chance of
+                // being the next one, which lowers the variance.
+                if accept > 0.0 {
+                    for (s, t, color) in &proposed {
+                        film.add_splat(
+                            *s,
+                            *t,
+                            *color * (accept * scale / proposed_y),
+                        );
+                    }
+                }
+                if accept < 1.0 {
+                    for (s, t, color) in &current {
+                        film.add_splat(
+                            *s,
+                            *t,
+                            *color * ((1.0 - accept) * scale / current_y),
+                        );
+                    }
+                }
+
+                if rng.gen::<f64>() < accept {
+                    sampler.accept();
+                    current = proposed;
+                    current_y = proposed_y;
+                } else {
+                    sampler.reject();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::PerspectiveCamera;
+    use crate::light::GradientSky;
+    use crate::material::Lambertian;
+    use crate::point::Point;
+    use crate::scene::Sphere;
+    use crate::vector::Vector3;
+
+    fn mlt(estimator: Estimator) -> Mlt {
+        Mlt {
+            estimator,
+            bootstrap: 2000,
+            chains: 8,
+            mutations_per_pixel: 200,
+            large_step_probability: 0.3,
+            sigma: 0.01,
+            spectral: false,
+        }
+    }
+
+    fn sphere() -> Sphere {
+        Sphere::new(
+            Point::origin(),
+            1.0,
+            Box::new(Lambertian {
+                albedo: Vector3 {
+                    x: 0.5,
+                    y: 0.6,
+                    z: 0.7,
+                },
+            }),
+        )
+    }
+
+    fn camera() -> PerspectiveCamera {
+        PerspectiveCamera::new(
+            Point {
+                x: 0.0,
+                y: 1.0,
+                z: 4.0,
+            },
+            Point::origin(),
+            Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            40.0,
+            1.0,
+            0.0,
+            4.0,
+        )
+    }
+
+    #[test]
+    fn test_same_seed_gives_the_same_contributions() {
+        let world = sphere();
+        let mut scene = Scene::new(&world);
+        scene.lights.push(Box::new(GradientSky));
+        let camera = camera();
+        for estimator in &[
+            Estimator::Path(PathSettings::default()),
+            Estimator::Bidirectional { max_depth: 5 },
+        ] {
+            let mlt = mlt(*estimator);
+            for seed in 0..20 {
+                let (_, first) = mlt.run(mlt.sampler(seed), &scene, &camera);
+                let (_, second) = mlt.run(mlt.sampler(seed), &scene, &camera);
+
+                assert_eq!(first, second, "{:?}, seed {}", estimator, seed);
+            }
+        }
+    }
+
+    /// The mean luminance of what `mlt` renders at `width` by `height`,
+    /// and an independent estimate of the bootstrap's `b`, from seeds the
+    /// bootstrap is unlikely to share.
+    fn image_and_bootstrap(mlt: &Mlt, width: u32, height: u32) -> (f64, f64) {
+        let world = sphere();
+        let mut scene = Scene::new(&world);
+        scene.lights.push(Box::new(GradientSky));
+        let camera = camera();
+        let mut film = Film::new(width, height);
+        mlt.render(&scene, &camera, &mut film);
+
+        let mut image = 0.0;
+        for y in 0..film.height {
+            for x in 0..film.width {
+                image += luminance(&film.pixel(x, y));
+            }
+        }
+        let image = image / f64::from(film.width * film.height);
+        let b = (0..mlt.bootstrap)
+            .map(|i| {
+                let sampler = mlt.sampler(u64::MAX - i as u64);
+                brightness(&mlt.run(sampler, &scene, &camera).1)
+            })
+            .sum::<f64>()
+            / mlt.bootstrap as f64;
+        (image, b)
+    }
+
+    #[test]
+    fn test_image_mean_matches_the_bootstrap() {
+        let mlt = mlt(Estimator::Path(PathSettings::default()));
+        let (image, b) = image_and_bootstrap(&mlt, 4, 4);
+
+        assert!(b > 0.0);
+        assert!((image - b).abs() < 0.05 * b, "{} vs {}", image, b);
+    }
+
+    #[test]
+    fn test_more_chains_than_mutations_still_fill_the_image() {
+        let mlt = Mlt {
+            chains: 10,
+            mutations_per_pixel: 1,
+            ..mlt(Estimator::Path(PathSettings::default()))
+        };
+        let (image, b) = image_and_bootstrap(&mlt, 2, 2);
+
+        assert!((image - b).abs() < 0.05 * b, "{} vs {}", image, b);
+    }
+}
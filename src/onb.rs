@@ -0,0 +1,108 @@
+use crate::vector::Vector3;
+
+/// An orthonormal basis built around `w`, used to move directions between
+/// world space and a local frame where `w` is the z axis.
+#[derive(Copy, Clone, Debug)]
+pub struct Onb {
+    pub u: Vector3,
+    pub v: Vector3,
+    pub w: Vector3,
+}
+
+impl Onb {
+    /// Builds a basis from a unit vector using the branchless construction
+    /// of Duff et al., which stays stable for any orientation of `w`.
+    pub fn from_w(w: Vector3) -> Self {
+        let sign = 1.0_f64.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        Onb {
+            u: Vector3 {
+                x: 1.0 + sign * w.x * w.x * a,
+                y: sign * b,
+                z: -sign * w.x,
+            },
+            v: Vector3 {
+                x: b,
+                y: sign + w.y * w.y * a,
+                z: -w.y,
+            },
+            w,
+        }
+    }
+
+    #[inline]
+    pub fn local(&self, a: &Vector3) -> Vector3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    #[inline]
+    pub fn to_local(&self, a: &Vector3) -> Vector3 {
+        Vector3 {
+            x: a.dot(&self.u),
+            y: a.dot(&self.v),
+            z: a.dot(&self.w),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_orthonormal() {
+        for w in &[
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            Vector3 {
+                x: 1.0,
+                y: 2.0,
+                z: -3.0,
+            }
+            .normalize(),
+        ] {
+            let onb = Onb::from_w(*w);
+            assert_close(onb.u.length(), 1.0);
+            assert_close(onb.v.length(), 1.0);
+            assert_close(onb.u.dot(&onb.v), 0.0);
+            assert_close(onb.u.dot(&onb.w), 0.0);
+            assert_close(onb.v.dot(&onb.w), 0.0);
+            assert_close(onb.u.cross(&onb.v).dot(&onb.w), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let onb = Onb::from_w(
+            Vector3 {
+                x: -0.3,
+                y: 0.5,
+                z: 0.2,
+            }
+            .normalize(),
+        );
+        let a = Vector3 {
+            x: 0.1,
+            y: -0.7,
+            z: 0.4,
+        };
+        let b = onb.local(&onb.to_local(&a));
+
+        assert_close(a.x, b.x);
+        assert_close(a.y, b.y);
+        assert_close(a.z, b.z);
+    }
+}
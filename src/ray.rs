@@ -1,13 +1,43 @@
+use crate::color::Color;
 use crate::point::Point;
+use crate::spectrum::{RGB_WAVELENGTHS, Wavelengths};
 use crate::vector::Vector3;
 
+#[derive(Copy, Clone, Debug)]
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector3,
+    /// Set when rendering spectrally, in which case every colour carried
+    /// along the ray holds one value per wavelength instead of RGB.
+    pub wavelengths: Option<Wavelengths>,
 }
 
 impl Ray {
     pub fn at(&self, t: f64) -> Point {
         self.origin + t * self.direction
     }
+
+    /// The wavelength, in nanometres, that each colour channel stands for.
+    pub fn channel_wavelengths(&self) -> [f64; 3] {
+        match self.wavelengths {
+            Some(w) => w.lambda,
+            None => RGB_WAVELENGTHS,
+        }
+    }
+
+    /// Brings an RGB reflectance into the space the ray is transported in.
+    pub fn upsample(&self, rgb: Vector3) -> Vector3 {
+        match self.wavelengths {
+            Some(w) => w.upsample(&rgb),
+            None => rgb,
+        }
+    }
+
+    /// Brings an RGB radiance into the space the ray is transported in.
+    pub fn upsample_color(&self, rgb: Color) -> Color {
+        match self.wavelengths {
+            Some(w) => w.upsample_color(&rgb),
+            None => rgb,
+        }
+    }
 }
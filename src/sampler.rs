@@ -0,0 +1,211 @@
+use std::cell::RefCell;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+/// One coordinate of a point in primary sample space, with the value it
+/// had before the current mutation so the mutation can be undone.
+#[derive(Copy, Clone, Default)]
+struct PrimarySample {
+    value: f64,
+    modified: u64,
+    backup_value: f64,
+    backup_modified: u64,
+}
+
+/// A point in primary sample space that Metropolis light transport moves
+/// around, after Kelemen et al. (2002) and pbrt. Coordinates are created as
+/// they are first drawn. Each iteration either replaces every coordinate,
+/// a large step, or perturbs each by a small normal offset. Coordinates
+/// that were not drawn for a while catch up on the mutations they missed
+/// when they are next drawn.
+pub struct PrimarySampler {
+    samples: Vec<PrimarySample>,
+    rng: StdRng,
+    sigma: f64,
+    large_step_probability: f64,
+    iteration: u64,
+    last_large_step: u64,
+    large_step: bool,
+    index: usize,
+}
+
+impl PrimarySampler {
+    /// A sampler whose coordinates are drawn from a generator seeded with
+    /// `seed`, so that two samplers with the same seed start at the same
+    /// point.
+    pub fn new(seed: u64, sigma: f64, large_step_probability: f64) -> Self {
+        PrimarySampler {
+            samples: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+            large_step_probability,
+            iteration: 0,
+            last_large_step: 0,
+            large_step: true,
+            index: 0,
+        }
+    }
+
+    /// Starts a new mutation, to be drawn lazily as coordinates are used.
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    /// Keeps the current mutation.
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Undoes the current mutation.
+    pub fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup_value;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    fn next(&mut self) -> f64 {
+        let (iteration, last_large_step) =
+            (self.iteration, self.last_large_step);
+        if self.index == self.samples.len() {
+            // A coordinate first drawn during a small step still starts out
+            // uniform, or a rejection sampling loop could never end.
+            self.samples.push(PrimarySample {
+                value: self.rng.gen(),
+                modified: last_large_step,
+                ..PrimarySample::default()
+            });
+        }
+        let sample = &mut self.samples[self.index];
+        self.index += 1;
+
+        if sample.modified < last_large_step {
+            sample.value = self.rng.gen();
+            sample.modified = last_large_step;
+        }
+        sample.backup_value = sample.value;
+        sample.backup_modified = sample.modified;
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else {
+            // A normal offset, widened for the small steps missed since the
+            // coordinate was last drawn, wrapped around into [0, 1).
+            let steps = (iteration - sample.modified) as f64;
+            let (u1, u2): (f64, f64) = (self.rng.gen(), self.rng.gen());
+            let normal = (-2.0 * (1.0 - u1).ln()).sqrt()
+                * (2.0 * std::f64::consts::PI * u2).cos();
+            sample.value += normal * self.sigma * steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.modified = iteration;
+        sample.value
+    }
+}
+
+thread_local! {
+    static PRIMARY: RefCell<Option<PrimarySampler>> =
+        const { RefCell::new(None) };
+}
+
+/// Runs `f` with every random number drawn through `rng` on this thread
+/// taken from `sampler`, so that what `f` computes is a function of the
+/// sampler's point in primary sample space. Hands the sampler back along
+/// with the result.
+pub fn drawing_from<R>(
+    sampler: PrimarySampler,
+    f: impl FnOnce() -> R,
+) -> (PrimarySampler, R) {
+    PRIMARY.with(|primary| *primary.borrow_mut() = Some(sampler));
+    let result = f();
+    let sampler = PRIMARY
+        .with(|primary| primary.borrow_mut().take())
+        .expect("primary sampler went missing");
+    (sampler, result)
+}
+
+/// The random number generator every part of the renderer draws from. It
+/// is the thread's usual generator unless `drawing_from` has put a
+/// primary sampler in its place.
+pub struct SampleRng {
+    fallback: ThreadRng,
+}
+
+pub fn rng() -> SampleRng {
+    SampleRng {
+        fallback: thread_rng(),
+    }
+}
+
+impl RngCore for SampleRng {
+    fn next_u32(&mut self) -> u32 {
+        match primary_sample() {
+            Some(u) => (u * 4_294_967_296.0) as u32,
+            None => self.fallback.next_u32(),
+        }
+    }
+
+    /// Keeps the top 53 bits exact, so that `gen::<f64>()` returns the
+    /// primary sample itself.
+    fn next_u64(&mut self) -> u64 {
+        match primary_sample() {
+            Some(u) => ((u * 9_007_199_254_740_992.0) as u64) << 11,
+            None => self.fallback.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+fn primary_sample() -> Option<f64> {
+    PRIMARY.with(|primary| primary.borrow_mut().as_mut().map(|s| s.next()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_floats_are_primary_samples() {
+        let sampler = PrimarySampler::new(7, 0.01, 0.3);
+        let (_, drawn): (_, Vec<f64>) =
+            drawing_from(sampler, || (0..4).map(|_| rng().gen()).collect());
+        let mut replay = PrimarySampler::new(7, 0.01, 0.3);
+        let replayed: Vec<f64> = (0..4).map(|_| replay.next()).collect();
+
+        assert_eq!(drawn, replayed);
+    }
+
+    #[test]
+    fn test_reject_restores_point() {
+        let mut sampler = PrimarySampler::new(3, 0.01, 0.3);
+        let before: Vec<f64> = (0..5).map(|_| sampler.next()).collect();
+        for _ in 0..10 {
+            sampler.start_iteration();
+            let mutated: Vec<f64> = (0..5).map(|_| sampler.next()).collect();
+            assert!(mutated.iter().all(|u| (0.0..1.0).contains(u)));
+            sampler.reject();
+        }
+        let values: Vec<f64> =
+            sampler.samples.iter().map(|s| s.value).collect();
+
+        assert_eq!(values, before);
+    }
+}
@@ -1,17 +1,199 @@
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+use rand::prelude::*;
+
+use crate::distribution::Distribution1D;
+use crate::light::{AreaLight, Emission, Light, LightSample};
+use crate::light_sampler::{LightSampler, LightSampling};
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::point::Point;
 use crate::ray::Ray;
+use crate::sampler::rng;
+use crate::texture::Texture;
+use crate::util::random_unit_vector;
 use crate::vector::Vector3;
 
+/// Where a ray met a surface. `normal` is the shading normal, which normal
+/// and bump maps may perturb, while `geometric_normal` always follows the
+/// true surface. `dpdu` and `dpdv` are the surface tangents along the `u`
+/// and `v` texture coordinates. `barycentrics` are the weights of the
+/// second and third vertices when a triangle was hit. `object` is the
+/// primitive that was hit, so materials can trace against their own shape.
+#[derive(Copy, Clone)]
 pub struct HitRecord<'a> {
     pub t: f64,
     pub p: Point,
     pub normal: Vector3,
+    pub geometric_normal: Vector3,
+    pub u: f64,
+    pub v: f64,
+    pub dpdu: Vector3,
+    pub dpdv: Vector3,
+    pub barycentrics: Option<(f64, f64)>,
     pub material: &'a dyn Material,
+    pub object: &'a dyn Hit,
+}
+
+impl<'a> HitRecord<'a> {
+    /// A starting point for a ray leaving in `direction`, nudged off the
+    /// surface along the geometric normal so it cannot hit it again.
+    pub fn offset_origin(&self, direction: &Vector3) -> Point {
+        let offset = 1e-4 * self.geometric_normal;
+        if direction.dot(&self.geometric_normal) > 0.0 {
+            self.p + offset
+        } else {
+            self.p - offset
+        }
+    }
 }
 
 pub trait Hit {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+
+    /// Lights for the emissive surfaces of this object, which `Scene::new`
+    /// collects so they are sampled directly.
+    fn lights(&self) -> Vec<Box<dyn Light + '_>> {
+        Vec::new()
+    }
+
+    /// A box around everything the object can hit, or `None` if it is
+    /// unbounded.
+    fn bounding_box(&self) -> Option<Bounds> {
+        None
+    }
+}
+
+/// A point picked on the surface of a shape to light another point.
+pub struct ShapeSample {
+    pub p: Point,
+    pub normal: Vector3,
+    /// Solid angle density of the direction towards `p`, as seen from the
+    /// point being lit.
+    pub pdf: f64,
+}
+
+/// An axis-aligned box.
+#[derive(Copy, Clone, Debug)]
+pub struct Bounds {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Bounds {
+    /// The smallest box containing all of `points`, which must not be
+    /// empty.
+    pub fn around(points: &[Point]) -> Self {
+        points[1..].iter().fold(
+            Bounds {
+                min: points[0],
+                max: points[0],
+            },
+            |b, p| b.union(&Bounds { min: *p, max: *p }),
+        )
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: Point {
+                x: self.min.x.min(other.min.x),
+                y: self.min.y.min(other.min.y),
+                z: self.min.z.min(other.min.z),
+            },
+            max: Point {
+                x: self.max.x.max(other.max.x),
+                y: self.max.y.max(other.max.y),
+                z: self.max.z.max(other.max.z),
+            },
+        }
+    }
+
+    pub fn center(&self) -> Point {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn diagonal(&self) -> Vector3 {
+        self.max - self.min
+    }
+}
+
+/// A surface that can be sampled from a point, so that it can emit light.
+pub trait Shape: Hit {
+    fn area(&self) -> f64;
+
+    fn bounds(&self) -> Bounds;
+
+    /// Picks a point uniformly over the surface, returning it with the
+    /// surface normal there.
+    fn sample_area(&self) -> (Point, Vector3);
+
+    /// Picks a point on the surface that can be seen from `p`, by default
+    /// uniformly by area.
+    fn sample(&self, p: &Point) -> Option<ShapeSample> {
+        let (q, normal) = self.sample_area();
+        Some(ShapeSample {
+            p: q,
+            normal,
+            pdf: solid_angle_pdf(1.0 / self.area(), p, &q, &normal),
+        })
+    }
+
+    /// The solid angle density with which `sample` would pick `direction`
+    /// from `p`. Shapes that keep the default `sample` can keep the
+    /// default.
+    fn pdf(&self, p: &Point, direction: &Vector3) -> f64 {
+        uniform_pdf(self, p, direction)
+    }
+}
+
+/// Converts a density over area at `q` to one over solid angle at `p`.
+fn solid_angle_pdf(
+    area_pdf: f64,
+    p: &Point,
+    q: &Point,
+    normal: &Vector3,
+) -> f64 {
+    let to = *q - *p;
+    let cosine = to.normalize().dot(normal).abs();
+    if cosine == 0.0 {
+        0.0
+    } else {
+        area_pdf * to.norm() / cosine
+    }
+}
+
+fn uniform_pdf<S: Shape + ?Sized>(
+    shape: &S,
+    p: &Point,
+    direction: &Vector3,
+) -> f64 {
+    let ray = Ray {
+        origin: *p,
+        direction: *direction,
+        wavelengths: None,
+    };
+    match shape.hit(&ray, 0.001, f64::INFINITY) {
+        Some(rec) => solid_angle_pdf(
+            1.0 / shape.area(),
+            p,
+            &rec.p,
+            &rec.geometric_normal,
+        ),
+        None => 0.0,
+    }
+}
+
+/// An area light for `shape` if `material` emits anything.
+fn emitters<'a>(
+    shape: &'a dyn Shape,
+    material: &dyn Material,
+) -> Vec<Box<dyn Light + 'a>> {
+    if material.is_emissive() {
+        vec![Box::new(AreaLight::new(shape, material))]
+    } else {
+        Vec::new()
+    }
 }
 
 pub struct Sphere {
@@ -46,7 +228,11 @@ fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
 }
 
 impl Sphere {
-    pub fn new(center: Point, radius: f64, material: Box<dyn Material>) -> Self {
+    pub fn new(
+        center: Point,
+        radius: f64,
+        material: Box<dyn Material>,
+    ) -> Self {
         Sphere {
             center,
             radius,
@@ -54,32 +240,620 @@ impl Sphere {
             radius2: radius * radius,
         }
     }
+
+    /// Spherical coordinates of `p`, with `u` running around the y axis and
+    /// `v` from the south pole to the north pole.
+    fn uv(&self, p: Point) -> (f64, f64) {
+        let d = (p - self.center) / self.radius;
+        let theta = (-d.y).clamp(-1.0, 1.0).acos();
+        let phi = (-d.z).atan2(d.x) + std::f64::consts::PI;
+        (
+            phi / (2.0 * std::f64::consts::PI),
+            theta / std::f64::consts::PI,
+        )
+    }
+
+    /// The derivatives of `p` with respect to the coordinates from `uv`.
+    fn tangents(&self, p: Point) -> (Vector3, Vector3) {
+        let d = (p - self.center) / self.radius;
+        let rho = (d.x * d.x + d.z * d.z).sqrt().max(1e-9);
+        let dpdu = (2.0 * std::f64::consts::PI * self.radius)
+            * Vector3 {
+                x: d.z,
+                y: 0.0,
+                z: -d.x,
+            };
+        let dpdv = (std::f64::consts::PI * self.radius)
+            * Vector3 {
+                x: -d.y * d.x / rho,
+                y: rho,
+                z: -d.y * d.z / rho,
+            };
+        (dpdu, dpdv)
+    }
 }
 
 impl Hit for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let direction = ray.origin - self.center;
         let a = ray.direction.norm();
         let b = direction.dot(&ray.direction);
         let c = direction.norm() - self.radius2;
         if let Some((t0, t1)) = solve_quadratic(a, b, c) {
-            if t0 > t_max || t1 < t_min {
+            let t = if t0 < t_min { t1 } else { t0 };
+            if t < t_min || t > t_max {
                 None
             } else {
-                let t = if t0 < t_min { t1 } else { t0 };
                 let p = ray.at(t);
+                let (u, v) = self.uv(p);
+                let (dpdu, dpdv) = self.tangents(p);
+                let normal =
+                    ((p - self.center) * self.radius.signum()).normalize();
                 Some(HitRecord {
                     t,
                     p,
-                    normal: ((p - self.center) * self.radius.signum())
-                        .normalize(),
+                    normal,
+                    geometric_normal: normal,
+                    u,
+                    v,
+                    dpdu,
+                    dpdv,
+                    barycentrics: None,
                     material: self.material.as_ref(),
+                    object: self,
                 })
             }
         } else {
             None
         }
     }
+
+    fn lights(&self) -> Vec<Box<dyn Light + '_>> {
+        emitters(self, self.material.as_ref())
+    }
+
+    fn bounding_box(&self) -> Option<Bounds> {
+        Some(Shape::bounds(self))
+    }
+}
+
+impl Shape for Sphere {
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius2
+    }
+
+    fn bounds(&self) -> Bounds {
+        let r = self.radius.abs();
+        let extent = Vector3 { x: r, y: r, z: r };
+        Bounds {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+
+    fn sample_area(&self) -> (Point, Vector3) {
+        let n = random_unit_vector();
+        (
+            self.center + self.radius.abs() * n,
+            self.radius.signum() * n,
+        )
+    }
+
+    /// Samples the cone the sphere covers from outside it, and the whole
+    /// surface uniformly from inside.
+    fn sample(&self, p: &Point) -> Option<ShapeSample> {
+        let to_center = self.center - *p;
+        let dist2 = to_center.norm();
+        if dist2 <= self.radius2 {
+            let (q, normal) = self.sample_area();
+            return Some(ShapeSample {
+                p: q,
+                normal,
+                pdf: solid_angle_pdf(1.0 / self.area(), p, &q, &normal),
+            });
+        }
+
+        let mut rng = rng();
+        let sin2_max = self.radius2 / dist2;
+        let cos_max = (1.0 - sin2_max).max(0.0).sqrt();
+        let cos_theta = 1.0 - rng.gen::<f64>() * (1.0 - cos_max);
+        let sin2_theta = 1.0 - cos_theta * cos_theta;
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let dist = dist2.sqrt();
+        let direction = Onb::from_w(to_center / dist).local(&Vector3 {
+            x: sin2_theta.sqrt() * phi.cos(),
+            y: sin2_theta.sqrt() * phi.sin(),
+            z: cos_theta,
+        });
+        let along = dist * cos_theta
+            - (self.radius2 - dist2 * sin2_theta).max(0.0).sqrt();
+        let q = *p + along * direction;
+        Some(ShapeSample {
+            p: q,
+            normal: ((q - self.center) * self.radius.signum()).normalize(),
+            pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)),
+        })
+    }
+
+    fn pdf(&self, p: &Point, direction: &Vector3) -> f64 {
+        let to_center = self.center - *p;
+        let dist2 = to_center.norm();
+        if dist2 <= self.radius2 {
+            return uniform_pdf(self, p, direction);
+        }
+        let cos_max = (1.0 - self.radius2 / dist2).max(0.0).sqrt();
+        let cosine = direction.normalize().dot(&(to_center / dist2.sqrt()));
+        if cosine >= cos_max {
+            1.0 / (2.0 * PI * (1.0 - cos_max))
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Picks a point uniformly on a triangle, returning it with the
+/// triangle's normal.
+fn sample_triangle(
+    vertices: &[Point; 3],
+    u1: f64,
+    u2: f64,
+) -> (Point, Vector3) {
+    let su = u1.sqrt();
+    let (b1, b2) = (u2 * su, 1.0 - su);
+    let p = vertices[0]
+        + b1 * (vertices[1] - vertices[0])
+        + b2 * (vertices[2] - vertices[0]);
+    let normal = (vertices[1] - vertices[0])
+        .cross(&(vertices[2] - vertices[0]))
+        .normalize();
+    (p, normal)
+}
+
+fn triangle_area(vertices: &[Point; 3]) -> f64 {
+    0.5 * (vertices[1] - vertices[0])
+        .cross(&(vertices[2] - vertices[0]))
+        .length()
+}
+
+/// A single triangle. Texture coordinates default to the corners of the
+/// unit square, and without per-vertex `normals` the triangle is shaded
+/// flat. The winding of `vertices` decides which side the normal faces.
+pub struct Triangle {
+    pub vertices: [Point; 3],
+    pub uvs: [(f64, f64); 3],
+    pub normals: Option<[Vector3; 3]>,
+    pub material: Box<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Point; 3], material: Box<dyn Material>) -> Self {
+        Triangle {
+            vertices,
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            normals: None,
+            material,
+        }
+    }
+}
+
+/// Intersects a triangle with the Möller–Trumbore algorithm, returning the
+/// distance along the ray and the barycentric weights of the second and
+/// third vertices.
+pub(crate) fn intersect_triangle(
+    vertices: &[Point; 3],
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let e1 = vertices[1] - vertices[0];
+    let e2 = vertices[2] - vertices[0];
+    let pvec = ray.direction.cross(&e2);
+    let det = e1.dot(&pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = ray.origin - vertices[0];
+    let b1 = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = tvec.cross(&e1);
+    let b2 = ray.direction.dot(&qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let t = e2.dot(&qvec) * inv_det;
+    if t < t_min || t > t_max {
+        None
+    } else {
+        Some((t, b1, b2))
+    }
+}
+
+/// Fills in a hit record for a point on a triangle given by its barycentric
+/// weights.
+pub(crate) fn triangle_record<'a>(
+    vertices: &[Point; 3],
+    uvs: &[(f64, f64); 3],
+    normals: Option<&[Vector3; 3]>,
+    material: &'a dyn Material,
+    object: &'a dyn Hit,
+    ray: &Ray,
+    (t, b1, b2): (f64, f64, f64),
+) -> HitRecord<'a> {
+    let b0 = 1.0 - b1 - b2;
+    let dp02 = vertices[0] - vertices[2];
+    let dp12 = vertices[1] - vertices[2];
+    let geometric_normal = (vertices[1] - vertices[0])
+        .cross(&(vertices[2] - vertices[0]))
+        .normalize();
+    let normal = match normals {
+        Some(n) => {
+            let n = (b0 * n[0] + b1 * n[1] + b2 * n[2]).normalize();
+            if n.dot(&geometric_normal) < 0.0 {
+                -n
+            } else {
+                n
+            }
+        }
+        None => geometric_normal,
+    };
+
+    let (du02, dv02) = (uvs[0].0 - uvs[2].0, uvs[0].1 - uvs[2].1);
+    let (du12, dv12) = (uvs[1].0 - uvs[2].0, uvs[1].1 - uvs[2].1);
+    let det = du02 * dv12 - dv02 * du12;
+    let (dpdu, dpdv) = if det.abs() < 1e-12 {
+        let onb = Onb::from_w(geometric_normal);
+        (onb.u, onb.v)
+    } else {
+        (
+            (dv12 * dp02 - dv02 * dp12) / det,
+            (du02 * dp12 - du12 * dp02) / det,
+        )
+    };
+
+    HitRecord {
+        t,
+        p: ray.at(t),
+        normal,
+        geometric_normal,
+        u: b0 * uvs[0].0 + b1 * uvs[1].0 + b2 * uvs[2].0,
+        v: b0 * uvs[0].1 + b1 * uvs[1].1 + b2 * uvs[2].1,
+        dpdu,
+        dpdv,
+        barycentrics: Some((b1, b2)),
+        material,
+        object,
+    }
+}
+
+impl Hit for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let hit = intersect_triangle(&self.vertices, ray, t_min, t_max)?;
+        Some(triangle_record(
+            &self.vertices,
+            &self.uvs,
+            self.normals.as_ref(),
+            self.material.as_ref(),
+            self,
+            ray,
+            hit,
+        ))
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Light + '_>> {
+        emitters(self, self.material.as_ref())
+    }
+
+    fn bounding_box(&self) -> Option<Bounds> {
+        Some(Shape::bounds(self))
+    }
+}
+
+impl Shape for Triangle {
+    fn area(&self) -> f64 {
+        triangle_area(&self.vertices)
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::around(&self.vertices)
+    }
+
+    fn sample_area(&self) -> (Point, Vector3) {
+        let mut rng = rng();
+        sample_triangle(&self.vertices, rng.gen(), rng.gen())
+    }
+}
+
+/// Triangles sharing vertices and a single material. Rays that hit any
+/// of them report the whole mesh as the hit object, so a closed mesh can be
+/// treated as one solid.
+pub struct Mesh {
+    pub vertices: Vec<Point>,
+    pub indices: Vec<[usize; 3]>,
+    pub normals: Option<Vec<Vector3>>,
+    pub uvs: Option<Vec<(f64, f64)>>,
+    pub material: Box<dyn Material>,
+    areas: OnceLock<Distribution1D>,
+}
+
+impl Mesh {
+    pub fn new(
+        vertices: Vec<Point>,
+        indices: Vec<[usize; 3]>,
+        material: Box<dyn Material>,
+    ) -> Self {
+        Mesh {
+            vertices,
+            indices,
+            normals: None,
+            uvs: None,
+            material,
+            areas: OnceLock::new(),
+        }
+    }
+
+    pub fn triangle(&self, i: usize) -> [Point; 3] {
+        let [a, b, c] = self.indices[i];
+        [self.vertices[a], self.vertices[b], self.vertices[c]]
+    }
+
+    /// Picks triangles in proportion to their area, built the first time
+    /// the mesh is sampled as a light.
+    fn areas(&self) -> &Distribution1D {
+        self.areas.get_or_init(|| {
+            Distribution1D::new(
+                (0..self.indices.len())
+                    .map(|i| triangle_area(&self.triangle(i)))
+                    .collect(),
+            )
+        })
+    }
+}
+
+impl Hit for Mesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut t_max = t_max;
+        for (i, indices) in self.indices.iter().enumerate() {
+            if let Some(hit) =
+                intersect_triangle(&self.triangle(i), ray, t_min, t_max)
+            {
+                t_max = hit.0;
+                closest = Some((indices, hit));
+            }
+        }
+
+        let ([a, b, c], hit) = closest?;
+        let uvs = match &self.uvs {
+            Some(uvs) => [uvs[*a], uvs[*b], uvs[*c]],
+            None => [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+        };
+        let normals = self.normals.as_ref().map(|n| [n[*a], n[*b], n[*c]]);
+        Some(triangle_record(
+            &[self.vertices[*a], self.vertices[*b], self.vertices[*c]],
+            &uvs,
+            normals.as_ref(),
+            self.material.as_ref(),
+            self,
+            ray,
+            hit,
+        ))
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Light + '_>> {
+        emitters(self, self.material.as_ref())
+    }
+
+    fn bounding_box(&self) -> Option<Bounds> {
+        Some(Shape::bounds(self))
+    }
+}
+
+impl Shape for Mesh {
+    fn area(&self) -> f64 {
+        let areas = self.areas();
+        areas.integral() * areas.len() as f64
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::around(&self.vertices)
+    }
+
+    fn sample_area(&self) -> (Point, Vector3) {
+        let mut rng = rng();
+        let i = self.areas().sample_discrete(rng.gen());
+        sample_triangle(&self.triangle(i), rng.gen(), rng.gen())
+    }
+}
+
+/// A parallelogram with one corner at `corner` and edges `u` and `v`, such
+/// as a rectangular light panel. Its normal points along `u × v`, and its
+/// texture coordinates run from zero to one along each edge.
+pub struct Quad {
+    pub corner: Point,
+    pub u: Vector3,
+    pub v: Vector3,
+    pub material: Box<dyn Material>,
+}
+
+impl Hit for Quad {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let n = self.u.cross(&self.v);
+        let denom = n.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = n.dot(&(self.corner - ray.origin)) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let p = ray.at(t);
+        let planar = p - self.corner;
+        let w = n / n.norm();
+        let a = w.dot(&planar.cross(&self.v));
+        let b = w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+        let normal = n.normalize();
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            geometric_normal: normal,
+            u: a,
+            v: b,
+            dpdu: self.u,
+            dpdv: self.v,
+            barycentrics: None,
+            material: self.material.as_ref(),
+            object: self,
+        })
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Light + '_>> {
+        emitters(self, self.material.as_ref())
+    }
+
+    fn bounding_box(&self) -> Option<Bounds> {
+        Some(Shape::bounds(self))
+    }
+}
+
+impl Shape for Quad {
+    fn area(&self) -> f64 {
+        self.u.cross(&self.v).length()
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::around(&[
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ])
+    }
+
+    fn sample_area(&self) -> (Point, Vector3) {
+        let mut rng = rng();
+        let q =
+            self.corner + rng.gen::<f64>() * self.u + rng.gen::<f64>() * self.v;
+        (q, self.u.cross(&self.v).normalize())
+    }
+}
+
+/// Cuts holes in any primitive using the red channel of `mask` as its
+/// opacity, for leaves and fences modelled as simple cards. Hits on fully
+/// transparent texels are skipped, and partial opacity lets a matching
+/// fraction of rays through, so camera and shadow rays alike see it.
+pub struct AlphaMask<T: Hit> {
+    pub inner: T,
+    pub mask: Box<dyn Texture>,
+}
+
+impl<T: Hit> Hit for AlphaMask<T> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rng = rng();
+        let mut t_min = t_min;
+        loop {
+            let rec = self.inner.hit(ray, t_min, t_max)?;
+            let alpha = self.mask.value(rec.u, rec.v, &rec.p).x;
+            if alpha >= 1.0 || (alpha > 0.0 && rng.gen::<f64>() < alpha) {
+                return Some(rec);
+            }
+            t_min = rec.t + 1e-6;
+        }
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Light + '_>> {
+        self.inner
+            .lights()
+            .into_iter()
+            .map(|light| -> Box<dyn Light + '_> {
+                Box::new(MaskedLight {
+                    light,
+                    mask: self.mask.as_ref(),
+                })
+            })
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Bounds> {
+        self.inner.bounding_box()
+    }
+}
+
+/// A light on a shape inside an `AlphaMask`, whose emission is scaled by
+/// the mask's opacity where it is sampled, as rays see the shape only that
+/// often there.
+struct MaskedLight<'a> {
+    light: Box<dyn Light + 'a>,
+    mask: &'a dyn Texture,
+}
+
+impl MaskedLight<'_> {
+    /// The mask's opacity where `ray` first meets the light's shape.
+    fn alpha(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let rec = self.light.shape()?.hit(ray, t_min, t_max)?;
+        Some(self.mask.value(rec.u, rec.v, &rec.p).x.clamp(0.0, 1.0))
+    }
+}
+
+impl Light for MaskedLight<'_> {
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let mut sample = self.light.sample(p)?;
+        let towards = Ray {
+            origin: *p,
+            direction: sample.direction,
+            wavelengths: None,
+        };
+        let reach = sample.distance * (1.0 + 1e-4) + 1e-4;
+        sample.radiance = self.alpha(&towards, 0.001, reach)? * sample.radiance;
+        Some(sample)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.light.is_delta()
+    }
+
+    fn pdf(&self, p: &Point, direction: &Vector3) -> f64 {
+        self.light.pdf(p, direction)
+    }
+
+    fn shape(&self) -> Option<&dyn Shape> {
+        self.light.shape()
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.light.bounds()
+    }
+
+    fn power(&self) -> f64 {
+        self.light.power()
+    }
+
+    fn sample_le(&self) -> Option<Emission> {
+        let mut emission = self.light.sample_le()?;
+        // Looks back at where the ray leaves, as `AreaLight` does.
+        let probe = Ray {
+            origin: emission.ray.origin + 1e-3 * emission.ray.direction,
+            direction: -emission.ray.direction,
+            wavelengths: None,
+        };
+        emission.radiance = self.alpha(&probe, 0.0, 2e-3)? * emission.radiance;
+        Some(emission)
+    }
+
+    fn pdf_le(
+        &self,
+        p: &Point,
+        normal: &Vector3,
+        direction: &Vector3,
+    ) -> (f64, f64) {
+        self.light.pdf_le(p, normal, direction)
+    }
 }
 
 #[derive(Default)]
@@ -98,7 +872,7 @@ impl<'a> HitList<'a> {
 }
 
 impl<'a> Hit for HitList<'a> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut closest: Option<HitRecord> = None;
         let mut t = t_max;
         for hitable in &self.data {
@@ -109,4 +883,407 @@ impl<'a> Hit for HitList<'a> {
         }
         closest
     }
+
+    fn lights(&self) -> Vec<Box<dyn Light + '_>> {
+        self.data
+            .iter()
+            .flat_map(|hitable| hitable.lights())
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Bounds> {
+        let mut boxes = self.data.iter().map(|hitable| hitable.bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |bounds, b| Some(bounds.union(&b?)))
+    }
+}
+
+/// Everything a renderer needs to light and trace a frame.
+pub struct Scene<'a> {
+    pub world: &'a dyn Hit,
+    pub lights: Vec<Box<dyn Light + 'a>>,
+    pub light_sampling: LightSampling,
+    sampler: OnceLock<LightSampler>,
+}
+
+impl<'a> Scene<'a> {
+    /// Starts the scene's lights with those of the emissive objects in
+    /// `world`.
+    pub fn new(world: &'a dyn Hit) -> Self {
+        Scene {
+            world,
+            lights: world.lights(),
+            light_sampling: LightSampling::Tree,
+            sampler: OnceLock::new(),
+        }
+    }
+
+    /// Picks lights for next event estimation. It is built from `lights`
+    /// and `light_sampling` the first time it is asked for, so both must be
+    /// settled before rendering starts.
+    pub fn light_sampler(&self) -> &LightSampler {
+        self.sampler.get_or_init(|| {
+            LightSampler::new(&self.lights, self.light_sampling)
+        })
+    }
+
+    /// Whether nothing blocks the segment leaving `p` along `direction` for
+    /// `distance`.
+    pub fn unoccluded(
+        &self,
+        p: Point,
+        direction: Vector3,
+        distance: f64,
+    ) -> bool {
+        let ray = Ray {
+            origin: p,
+            direction,
+            wavelengths: None,
+        };
+        self.world
+            .hit(&ray, 0.001, distance * (1.0 - 1e-6))
+            .is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    fn material() -> Box<dyn Material> {
+        Box::new(Lambertian {
+            albedo: Vector3::zero(),
+        })
+    }
+
+    fn ray(origin: Point, direction: Vector3) -> Ray {
+        Ray {
+            origin,
+            direction,
+            wavelengths: None,
+        }
+    }
+
+    #[test]
+    fn test_triangle_hit_interpolates_uvs() {
+        let mut triangle = Triangle::new(
+            [
+                Point::origin(),
+                Point {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                Point {
+                    x: 0.0,
+                    y: 1.0,
+                    z: 0.0,
+                },
+            ],
+            material(),
+        );
+        triangle.uvs = [(0.0, 0.0), (2.0, 0.0), (0.0, 4.0)];
+        let down = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
+        };
+        let rec = triangle
+            .hit(
+                &ray(
+                    Point {
+                        x: 0.25,
+                        y: 0.5,
+                        z: 1.0,
+                    },
+                    down,
+                ),
+                0.001,
+                f64::INFINITY,
+            )
+            .unwrap();
+
+        assert!((rec.t - 1.0).abs() < 1e-12);
+        let (b1, b2) = rec.barycentrics.unwrap();
+        assert!((b1 - 0.25).abs() < 1e-12 && (b2 - 0.5).abs() < 1e-12);
+        assert!((rec.u - 0.5).abs() < 1e-12 && (rec.v - 2.0).abs() < 1e-12);
+        assert!((rec.dpdu.x - 0.5).abs() < 1e-12 && rec.dpdu.y.abs() < 1e-12);
+        assert!((rec.dpdv.y - 0.25).abs() < 1e-12 && rec.dpdv.x.abs() < 1e-12);
+        assert_eq!(rec.geometric_normal, -down);
+        assert!(
+            triangle
+                .hit(
+                    &ray(
+                        Point {
+                            x: 0.75,
+                            y: 0.5,
+                            z: 1.0
+                        },
+                        down
+                    ),
+                    0.001,
+                    f64::INFINITY
+                )
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_sphere_tangents_are_orthogonal() {
+        let sphere = Sphere::new(Point::origin(), 2.0, material());
+        let target = Point {
+            x: 0.3,
+            y: 0.4,
+            z: -0.2,
+        };
+        let origin = Point {
+            x: 5.0,
+            y: 3.0,
+            z: 1.0,
+        };
+        let rec = sphere
+            .hit(&ray(origin, target - origin), 0.001, f64::INFINITY)
+            .unwrap();
+
+        assert!(rec.dpdu.dot(&rec.dpdv).abs() < 1e-9);
+        assert!(rec.dpdu.dot(&rec.normal).abs() < 1e-9);
+        assert!(rec.dpdv.dot(&rec.normal).abs() < 1e-9);
+        // The frame is right handed about the outward normal.
+        assert!(rec.dpdu.cross(&rec.dpdv).dot(&rec.normal) > 0.0);
+    }
+
+    #[test]
+    fn test_sphere_hit_respects_t_max_from_inside() {
+        let sphere = Sphere::new(Point::origin(), 1.0, material());
+        let inside = ray(
+            Point::origin(),
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        );
+
+        assert!(sphere.hit(&inside, 0.001, 0.5).is_none());
+        assert!(sphere.hit(&inside, 0.001, 1.5).is_some());
+    }
+
+    /// A triangular card at z = 2 with the given opacity, in front of a
+    /// sphere at the origin.
+    fn card_before_sphere(alpha: f64) -> HitList<'static> {
+        let mut list = HitList::new();
+        list.push(AlphaMask {
+            inner: Triangle::new(
+                [
+                    Point {
+                        x: -1.0,
+                        y: -1.0,
+                        z: 2.0,
+                    },
+                    Point {
+                        x: 1.0,
+                        y: -1.0,
+                        z: 2.0,
+                    },
+                    Point {
+                        x: 0.0,
+                        y: 1.0,
+                        z: 2.0,
+                    },
+                ],
+                material(),
+            ),
+            mask: Box::new(crate::texture::ConstantTexture {
+                color: Vector3 {
+                    x: alpha,
+                    y: alpha,
+                    z: alpha,
+                },
+            }),
+        });
+        list.push(Sphere::new(Point::origin(), 1.0, material()));
+        list
+    }
+
+    #[test]
+    fn test_alpha_mask_cuts_out_or_keeps_the_inner_hit() {
+        let towards = ray(
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: 5.0,
+            },
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+        );
+        let (clear, opaque) =
+            (card_before_sphere(0.0), card_before_sphere(1.0));
+        let through = clear.hit(&towards, 0.001, f64::INFINITY).unwrap();
+        let blocked = opaque.hit(&towards, 0.001, f64::INFINITY).unwrap();
+
+        assert!((through.t - 4.0).abs() < 1e-9);
+        assert!((blocked.t - 3.0).abs() < 1e-9);
+    }
+
+    fn assert_consistent(shape: &dyn Shape, p: &Point) {
+        for _ in 0..100 {
+            let sample = shape.sample(p).unwrap();
+            let direction = (sample.p - *p).normalize();
+            let pdf = shape.pdf(p, &direction);
+
+            assert!(
+                (sample.pdf - pdf).abs() < 1e-6 * pdf,
+                "{} != {}",
+                sample.pdf,
+                pdf
+            );
+        }
+    }
+
+    #[test]
+    fn test_sample_pdf_matches_pdf() {
+        let p = Point {
+            x: 0.3,
+            y: -2.0,
+            z: 0.1,
+        };
+        let sphere = Sphere::new(Point::origin(), 1.0, material());
+        let quad = Quad {
+            corner: Point::origin(),
+            u: Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            v: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 2.0,
+            },
+            material: material(),
+        };
+        let triangle = Triangle::new(
+            [
+                Point::origin(),
+                Point {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                Point {
+                    x: 0.0,
+                    y: 0.5,
+                    z: 1.0,
+                },
+            ],
+            material(),
+        );
+
+        assert_consistent(&sphere, &p);
+        assert_consistent(&sphere, &Point::origin());
+        assert_consistent(&quad, &p);
+        assert_consistent(&triangle, &p);
+    }
+
+    #[test]
+    fn test_alpha_mask_keeps_the_inner_lights() {
+        let glowing = AlphaMask {
+            inner: Sphere::new(
+                Point::origin(),
+                1.0,
+                Box::new(crate::material::DiffuseLight {
+                    emit: Box::new(crate::texture::ConstantTexture {
+                        color: Vector3 {
+                            x: 1.0,
+                            y: 1.0,
+                            z: 1.0,
+                        },
+                    }),
+                }),
+            ),
+            mask: Box::new(crate::texture::ConstantTexture {
+                color: Vector3 {
+                    x: 0.5,
+                    y: 0.5,
+                    z: 0.5,
+                },
+            }),
+        };
+        let lights = glowing.lights();
+        let unmasked = glowing.inner.lights();
+        let p = Point {
+            x: 0.0,
+            y: 0.0,
+            z: 3.0,
+        };
+
+        assert_eq!(lights.len(), 1);
+        // Rays see the half opaque glow half the time, so a light sample
+        // carries half of it.
+        let sample = lights[0].sample(&p).unwrap();
+        let full = unmasked[0].sample(&p).unwrap();
+        assert!((sample.radiance.r - 0.5 * full.radiance.r).abs() < 1e-9);
+        let emission = lights[0].sample_le().unwrap();
+        assert!((emission.radiance.g - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mesh_area_sums_triangles() {
+        let vertices = vec![
+            Point::origin(),
+            Point {
+                x: 2.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Point {
+                x: 0.0,
+                y: 2.0,
+                z: 0.0,
+            },
+            Point {
+                x: 2.0,
+                y: 2.0,
+                z: 0.0,
+            },
+        ];
+        let mesh = Mesh::new(vertices, vec![[0, 1, 2], [1, 3, 2]], material());
+
+        assert!((mesh.area() - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_hit_list_bounds_its_children() {
+        let mut list = HitList::new();
+        assert!(list.bounding_box().is_none());
+
+        list.push(Sphere::new(Point::origin(), 1.0, material()));
+        list.push(Quad {
+            corner: Point {
+                x: 2.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            u: Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            v: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 3.0,
+            },
+            material: material(),
+        });
+        let bounds = list.bounding_box().unwrap();
+
+        assert_eq!(bounds.min.x, -1.0);
+        assert_eq!(bounds.max.x, 3.0);
+        assert_eq!(bounds.max.z, 3.0);
+    }
 }
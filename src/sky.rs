@@ -0,0 +1,379 @@
+use std::f64::consts::PI;
+
+use image::{Rgb, Rgb32FImage};
+use rand::prelude::*;
+
+use crate::color::Color;
+use crate::light::{EnvironmentMap, Light, LightSample, equirect_direction};
+use crate::onb::Onb;
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::sampler::rng;
+use crate::spectrum::xyz_to_rgb;
+use crate::util::black;
+use crate::vector::Vector3;
+
+/// The angular radius of the sun seen from the earth, in radians.
+pub const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+
+/// The luminance of the sun outside the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+/// The wavelengths, in micrometres, at which the sun's transmittance through
+/// the atmosphere is evaluated for the red, green and blue channels.
+const SUN_WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
+
+/// Resolution of the table the sky is importance sampled from.
+const TABLE_WIDTH: u32 = 256;
+const TABLE_HEIGHT: u32 = 128;
+
+/// The Perez distribution of one of `Y`, `x` and `y` over the sky.
+#[derive(Copy, Clone, Debug)]
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta.max(1e-3)).exp())
+            * (1.0
+                + self.c * (self.d * gamma).exp()
+                + self.e * gamma.cos() * gamma.cos())
+    }
+}
+
+/// The daylight sky of Preetham, Shirley and Smits (1999) for a sun in
+/// `sun_direction`. `turbidity` describes the haze in the air, from about
+/// 2 for a clear day to 10 for a hazy one. Below the horizon the sky is
+/// replaced by a diffuse ground of `ground_albedo` lit by the sky and sun.
+///
+/// Radiance is in kcd/m² times `intensity`. The sun itself is not part of
+/// the sky and is added to a scene separately with `sun`.
+pub struct PreethamSky {
+    model: SkyModel,
+    map: EnvironmentMap,
+}
+
+/// The analytic model behind a `PreethamSky`, which its table is baked
+/// from.
+struct SkyModel {
+    turbidity: f64,
+    ground_albedo: Vector3,
+    intensity: f64,
+    sun_direction: Vector3,
+    theta_sun: f64,
+    perez: [Perez; 3],
+    zenith: [f64; 3],
+    ground: Color,
+}
+
+impl PreethamSky {
+    pub fn new(
+        sun_direction: Vector3,
+        turbidity: f64,
+        ground_albedo: Vector3,
+        intensity: f64,
+    ) -> Self {
+        let t = turbidity;
+        let sun_direction = sun_direction.normalize();
+        // The model only holds for a sun above the horizon.
+        let theta_sun = sun_direction.y.clamp(0.0, 1.0).acos();
+        let perez = [
+            Perez {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            Perez {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            Perez {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let th = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+            let tu = [t * t, t, 1.0];
+            (0..3)
+                .map(|i| tu[i] * (0..4).map(|j| m[i][j] * th[j]).sum::<f64>())
+                .sum::<f64>()
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let mut model = SkyModel {
+            turbidity,
+            ground_albedo,
+            intensity,
+            sun_direction,
+            theta_sun,
+            perez,
+            zenith: [luminance.max(0.0), x, y],
+            ground: black(),
+        };
+        model.ground = model.ground_radiance();
+
+        let image = Rgb32FImage::from_fn(TABLE_WIDTH, TABLE_HEIGHT, |x, y| {
+            let c = model.radiance(&equirect_direction(
+                (f64::from(x) + 0.5) / f64::from(TABLE_WIDTH),
+                (f64::from(y) + 0.5) / f64::from(TABLE_HEIGHT),
+            ));
+            Rgb([c.r as f32, c.g as f32, c.b as f32])
+        });
+        PreethamSky {
+            model,
+            map: EnvironmentMap::new(image, 0.0, 1.0),
+        }
+    }
+
+    /// The radiance arriving from `direction`, which is the ground below the
+    /// horizon.
+    pub fn radiance(&self, direction: &Vector3) -> Color {
+        self.model.radiance(direction)
+    }
+
+    /// The sun matching this sky, dimmed and reddened by the atmosphere it
+    /// shines through. It gives no light once it has set.
+    pub fn sun(&self) -> SunLight {
+        self.model.sun()
+    }
+}
+
+impl SkyModel {
+    /// The radiance of the sky above the horizon in `direction`.
+    fn sky_radiance(&self, direction: &Vector3) -> Color {
+        let d = direction.normalize();
+        let gamma = d.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let value = |i: usize| {
+            self.zenith[i] * self.perez[i].f(d.y, gamma)
+                / self.perez[i].f(1.0, self.theta_sun)
+        };
+        let (luminance, x, y) = (value(0), value(1), value(2));
+        if y <= 0.0 {
+            return black();
+        }
+        let xyz = Vector3 {
+            x: x / y * luminance,
+            y: luminance,
+            z: (1.0 - x - y) / y * luminance,
+        };
+        self.intensity * xyz_to_rgb(&xyz)
+    }
+
+    /// The radiance of the diffuse ground, from the irradiance the sky and
+    /// sun deliver to a horizontal surface.
+    fn ground_radiance(&self) -> Color {
+        let (width, height) = (TABLE_WIDTH / 2, TABLE_HEIGHT / 4);
+        let sun = self.sun();
+        let mut irradiance =
+            sun.radiance * (sun.solid_angle() * self.sun_direction.y.max(0.0));
+        for y in 0..height {
+            let theta = 0.5 * PI * (f64::from(y) + 0.5) / f64::from(height);
+            let solid_angle = 2.0 * PI / f64::from(width) * 0.5 * PI
+                / f64::from(height)
+                * theta.sin();
+            for x in 0..width {
+                let phi = 2.0 * PI * (f64::from(x) + 0.5) / f64::from(width);
+                let direction = Vector3 {
+                    x: theta.sin() * phi.cos(),
+                    y: theta.cos(),
+                    z: theta.sin() * phi.sin(),
+                };
+                irradiance = irradiance
+                    + self.sky_radiance(&direction)
+                        * (theta.cos() * solid_angle);
+            }
+        }
+        Color {
+            r: self.ground_albedo.x * irradiance.r / PI,
+            g: self.ground_albedo.y * irradiance.g / PI,
+            b: self.ground_albedo.z * irradiance.b / PI,
+        }
+    }
+
+    fn radiance(&self, direction: &Vector3) -> Color {
+        if direction.y < 0.0 {
+            self.ground
+        } else {
+            self.sky_radiance(direction)
+        }
+    }
+
+    fn sun(&self) -> SunLight {
+        let elevation = self.sun_direction.y;
+        let mut radiance = black();
+        if elevation > 0.0 {
+            // Relative optical mass of the air along the sun's rays.
+            let degrees = self.theta_sun.to_degrees();
+            let m = 1.0
+                / (self.theta_sun.cos()
+                    + 0.15 * (93.885 - degrees).powf(-1.253));
+            let beta = 0.04608 * self.turbidity - 0.04586;
+            let tau = |lambda: f64| {
+                let rayleigh = (-0.008735 * lambda.powf(-4.08) * m).exp();
+                let aerosol = (-beta * lambda.powf(-1.3) * m).exp();
+                rayleigh * aerosol
+            };
+            let scale = self.intensity * SUN_LUMINANCE;
+            radiance = Color {
+                r: scale * tau(SUN_WAVELENGTHS[0]),
+                g: scale * tau(SUN_WAVELENGTHS[1]),
+                b: scale * tau(SUN_WAVELENGTHS[2]),
+            };
+        }
+        SunLight::new(self.sun_direction, radiance, SUN_ANGULAR_RADIUS)
+    }
+}
+
+impl Light for PreethamSky {
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let sample = self.map.sample(p)?;
+        Some(LightSample {
+            radiance: self.radiance(&sample.direction),
+            ..sample
+        })
+    }
+
+    fn pdf(&self, p: &Point, direction: &Vector3) -> f64 {
+        self.map.pdf(p, direction)
+    }
+
+    fn le(&self, ray: &Ray) -> Color {
+        self.radiance(&ray.direction)
+    }
+}
+
+/// A distant disk of constant `radiance`, such as the sun, seen at
+/// `angular_radius` radians around `direction`. Directions are sampled
+/// uniformly within the cone it covers.
+pub struct SunLight {
+    pub direction: Vector3,
+    pub radiance: Color,
+    frame: Onb,
+    cos_max: f64,
+}
+
+impl SunLight {
+    pub fn new(
+        direction: Vector3,
+        radiance: Color,
+        angular_radius: f64,
+    ) -> Self {
+        let direction = direction.normalize();
+        SunLight {
+            direction,
+            radiance,
+            frame: Onb::from_w(direction),
+            cos_max: angular_radius.cos(),
+        }
+    }
+
+    /// The solid angle covered by the disk.
+    pub fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_max)
+    }
+}
+
+impl Light for SunLight {
+    fn sample(&self, _p: &Point) -> Option<LightSample> {
+        let mut rng = rng();
+        let cos_theta = 1.0 - rng.gen::<f64>() * (1.0 - self.cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        Some(LightSample {
+            direction: self.frame.local(&Vector3 {
+                x: sin_theta * phi.cos(),
+                y: sin_theta * phi.sin(),
+                z: cos_theta,
+            }),
+            distance: f64::INFINITY,
+            radiance: self.radiance,
+            pdf: 1.0 / self.solid_angle(),
+        })
+    }
+
+    fn pdf(&self, _p: &Point, direction: &Vector3) -> f64 {
+        if direction.normalize().dot(&self.direction) >= self.cos_max {
+            1.0 / self.solid_angle()
+        } else {
+            0.0
+        }
+    }
+
+    fn le(&self, ray: &Ray) -> Color {
+        if ray.direction.normalize().dot(&self.direction) >= self.cos_max {
+            self.radiance
+        } else {
+            black()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sun_at(elevation: f64) -> Vector3 {
+        let e = elevation.to_radians();
+        Vector3 {
+            x: e.cos(),
+            y: e.sin(),
+            z: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_clear_sky_is_blue_at_zenith() {
+        let sky = PreethamSky::new(sun_at(40.0), 2.5, Vector3::zero(), 1.0);
+        let zenith = sky.radiance(&Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        });
+
+        assert!(zenith.b > zenith.r, "{:?}", zenith);
+        assert!(zenith.g > 0.0);
+    }
+
+    #[test]
+    fn test_sun_reddens_towards_horizon() {
+        let high = PreethamSky::new(sun_at(60.0), 3.0, Vector3::zero(), 1.0);
+        let low = PreethamSky::new(sun_at(5.0), 3.0, Vector3::zero(), 1.0);
+        let ratio = |c: Color| c.b / c.r;
+
+        assert!(ratio(low.sun().radiance) < ratio(high.sun().radiance));
+        assert!(low.sun().radiance.g < high.sun().radiance.g);
+        assert_eq!(
+            PreethamSky::new(sun_at(-5.0), 3.0, Vector3::zero(), 1.0)
+                .sun()
+                .radiance
+                .g,
+            0.0
+        );
+    }
+}
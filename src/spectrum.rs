@@ -0,0 +1,230 @@
+use std::sync::OnceLock;
+
+use crate::color::Color;
+use crate::vector::Vector3;
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+/// The number of wavelengths carried by each path. They travel in the three
+/// channels of the `Color` and `Vector3` values the renderer already uses
+/// for RGB transport.
+pub const SAMPLES: usize = 3;
+
+/// Representative wavelengths of the red, green and blue channels, for
+/// effects that need a wavelength when rendering in RGB.
+pub const RGB_WAVELENGTHS: [f64; SAMPLES] = [650.0, 550.0, 450.0];
+
+/// The wavelengths, in nanometres, traced along one path. The first is the
+/// hero wavelength and the others are spread evenly across the visible
+/// range from it.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Wavelengths {
+    pub lambda: [f64; SAMPLES],
+    pub terminated: bool,
+}
+
+impl Wavelengths {
+    /// Picks the hero wavelength from a uniform number `u` in [0, 1).
+    pub fn sample(u: f64) -> Self {
+        let mut lambda = [0.0; SAMPLES];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f64 / SAMPLES as f64).fract();
+            *l = LAMBDA_MIN + offset * (LAMBDA_MAX - LAMBDA_MIN);
+        }
+        Wavelengths {
+            lambda,
+            terminated: false,
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn pdf(&self) -> f64 {
+        1.0 / (LAMBDA_MAX - LAMBDA_MIN)
+    }
+
+    /// Drops the secondary wavelengths once the path has taken a direction
+    /// that only makes sense for the hero, such as a dispersive refraction.
+    /// Returns the wavelengths to carry on with and the weight to apply to
+    /// the path so its estimate stays unbiased.
+    pub fn terminate_secondary(&self) -> (Wavelengths, Vector3) {
+        if self.terminated {
+            return (
+                *self,
+                Vector3 {
+                    x: 1.0,
+                    y: 1.0,
+                    z: 1.0,
+                },
+            );
+        }
+        (
+            Wavelengths {
+                terminated: true,
+                ..*self
+            },
+            Vector3 {
+                x: SAMPLES as f64,
+                y: 0.0,
+                z: 0.0,
+            },
+        )
+    }
+
+    /// The value of the reflectance spectrum of `rgb` at each wavelength.
+    pub fn upsample(&self, rgb: &Vector3) -> Vector3 {
+        Vector3 {
+            x: rgb_to_spectrum(rgb.x, rgb.y, rgb.z, self.lambda[0]),
+            y: rgb_to_spectrum(rgb.x, rgb.y, rgb.z, self.lambda[1]),
+            z: rgb_to_spectrum(rgb.x, rgb.y, rgb.z, self.lambda[2]),
+        }
+    }
+
+    pub fn upsample_color(&self, rgb: &Color) -> Color {
+        Color {
+            r: rgb_to_spectrum(rgb.r, rgb.g, rgb.b, self.lambda[0]),
+            g: rgb_to_spectrum(rgb.r, rgb.g, rgb.b, self.lambda[1]),
+            b: rgb_to_spectrum(rgb.r, rgb.g, rgb.b, self.lambda[2]),
+        }
+    }
+
+    /// Estimates the CIE XYZ colour of a path from its radiance at each
+    /// wavelength, normalised so that a flat spectrum of one has a `Y` of
+    /// one.
+    pub fn to_xyz(&self, radiance: &Color) -> Vector3 {
+        let values = [radiance.r, radiance.g, radiance.b];
+        let mut xyz = Vector3::zero();
+        for (l, value) in self.lambda.iter().zip(values.iter()) {
+            xyz = xyz + *value * cie_xyz(*l);
+        }
+        xyz / (self.pdf() * SAMPLES as f64 * integrals().y)
+    }
+}
+
+fn logistic(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// A smooth spectrum for an RGB triple, built from three bands that always
+/// sum to one so that white stays flat and albedos below one stay below one.
+pub fn rgb_to_spectrum(r: f64, g: f64, b: f64, lambda: f64) -> f64 {
+    let blue = 1.0 - logistic((lambda - 490.0) / 10.0);
+    let red = logistic((lambda - 585.0) / 10.0);
+    r * red + g * (1.0 - blue - red) + b * blue
+}
+
+fn lobe(lambda: f64, mu: f64, sigma_low: f64, sigma_high: f64) -> f64 {
+    let sigma = if lambda < mu { sigma_low } else { sigma_high };
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// The CIE 1931 colour matching functions, using the multi-lobe fit of
+/// Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(lambda: f64) -> Vector3 {
+    Vector3 {
+        x: 1.056 * lobe(lambda, 599.8, 37.9, 31.0)
+            + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        y: 0.821 * lobe(lambda, 568.8, 46.9, 40.5)
+            + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        z: 1.217 * lobe(lambda, 437.0, 11.8, 36.0)
+            + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    }
+}
+
+/// The integrals of the colour matching functions over the sampled range.
+fn integrals() -> &'static Vector3 {
+    static INTEGRALS: OnceLock<Vector3> = OnceLock::new();
+    INTEGRALS.get_or_init(|| {
+        let steps = 4700;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / f64::from(steps);
+        (0..steps).fold(Vector3::zero(), |sum, i| {
+            sum + step * cie_xyz(LAMBDA_MIN + (f64::from(i) + 0.5) * step)
+        })
+    })
+}
+
+fn xyz_to_linear_srgb(xyz: &Vector3) -> Color {
+    Color {
+        r: 3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        g: -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        b: 0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    }
+}
+
+/// Converts XYZ to linear sRGB, white balanced so that a flat spectrum maps
+/// to equal RGB values.
+pub fn xyz_to_rgb(xyz: &Vector3) -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        let integrals = integrals();
+        xyz_to_linear_srgb(&(*integrals / integrals.y))
+    });
+    let rgb = xyz_to_linear_srgb(xyz);
+    Color {
+        r: rgb.r / white.r,
+        g: rgb.g / white.g,
+        b: rgb.b / white.b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64, eps: f64) {
+        assert!((a - b).abs() < eps, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_sample_stays_in_range() {
+        for u in &[0.0, 0.3, 0.999] {
+            let w = Wavelengths::sample(*u);
+            for l in &w.lambda {
+                assert!(*l >= LAMBDA_MIN && *l < LAMBDA_MAX);
+            }
+        }
+    }
+
+    #[test]
+    fn test_white_is_flat() {
+        for l in &[380.0, 490.0, 550.0, 585.0, 700.0] {
+            assert_close(rgb_to_spectrum(1.0, 1.0, 1.0, *l), 1.0, 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_flat_spectrum_is_white() {
+        let white = Color {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+        };
+        let steps = 1000;
+        let mut sum = Vector3::zero();
+        for i in 0..steps {
+            let w =
+                Wavelengths::sample((f64::from(i) + 0.5) / f64::from(steps));
+            sum = sum + w.to_xyz(&white);
+        }
+        let rgb = xyz_to_rgb(&(sum / f64::from(steps)));
+
+        assert_close(rgb.r, 1.0, 1e-3);
+        assert_close(rgb.g, 1.0, 1e-3);
+        assert_close(rgb.b, 1.0, 1e-3);
+    }
+
+    #[test]
+    fn test_terminate_secondary() {
+        let (w, weight) = Wavelengths::sample(0.5).terminate_secondary();
+
+        assert!(w.terminated);
+        assert_eq!(weight.x, SAMPLES as f64);
+        assert_eq!(weight.y, 0.0);
+        assert_eq!(w.terminate_secondary().1.x, 1.0);
+    }
+}
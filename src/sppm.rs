@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+
+use rand::prelude::*;
+
+use crate::camera::{Camera, CameraRay};
+use crate::color::Color;
+use crate::film::Film;
+use crate::integrator::Integrator;
+use crate::light::sample_infinite_le;
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::sampler::rng;
+use crate::scene::{Bounds, HitRecord, Scene};
+use crate::spectrum::{Wavelengths, xyz_to_rgb};
+use crate::util::{black, emitted, escaped, sample_light, to_vector, weighted};
+use crate::vector::Vector3;
+
+/// How much of the photons gathered in a pass are kept when the search
+/// radius shrinks, as in Hachisuka et al. (2008).
+const ALPHA: f64 = 2.0 / 3.0;
+
+/// Stochastic progressive photon mapping, after Hachisuka and Jensen
+/// (2009) as in pbrt. Each pass traces a camera ray through every pixel,
+/// following specular bounces to a visible point on a surface that is not
+/// purely specular, then traces `photons` paths from the lights and gathers
+/// those landing within each visible point's search radius. The radii
+/// shrink from `initial_radius` as passes go on, so the estimate converges,
+/// and paths through glass onto diffuse surfaces, which the other
+/// integrators struggle to find, are resolved like any other light.
+///
+/// Light arriving at visible points directly from the lights is estimated
+/// as the path tracer does instead of from photons.
+pub struct Sppm {
+    pub iterations: u32,
+    /// Photons traced in each pass.
+    pub photons: usize,
+    pub initial_radius: f64,
+    /// The most bounces followed by camera paths and photons.
+    pub max_depth: usize,
+    pub spectral: bool,
+}
+
+/// Where a camera path ended up in the current pass, and its throughput.
+struct VisiblePoint<'a> {
+    rec: HitRecord<'a>,
+    ray: Ray,
+    beta: Vector3,
+}
+
+struct Pixel<'a> {
+    /// Directly lit radiance summed over the passes.
+    direct: Color,
+    radius: f64,
+    /// The photon count the accumulated flux `tau` stands for.
+    n: f64,
+    tau: Color,
+    visible: Option<VisiblePoint<'a>>,
+    /// Flux and photon count gathered in the current pass.
+    phi: Color,
+    m: u64,
+}
+
+impl Pixel<'_> {
+    /// Folds the photons of the pass just traced, whose flux is `phi`, into
+    /// the running estimate. Only `ALPHA` of them are kept, and the radius
+    /// shrinks so that the flux density stays the same.
+    fn end_pass(&mut self, phi: Color) {
+        if self.m > 0 {
+            let n = self.n + ALPHA * self.m as f64;
+            let radius = self.radius * (n / (self.n + self.m as f64)).sqrt();
+            let shrink = (radius * radius) / (self.radius * self.radius);
+            self.tau = (self.tau + phi) * shrink;
+            self.n = n;
+            self.radius = radius;
+        }
+        self.phi = black();
+        self.m = 0;
+        self.visible = None;
+    }
+}
+
+/// The visible points of a pass, bucketed into cubes at least as wide as
+/// any point's search diameter so each photon only looks in one cube.
+struct Grid {
+    size: f64,
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+impl Grid {
+    fn new(pixels: &[Pixel]) -> Self {
+        let max_radius = pixels
+            .iter()
+            .filter(|pixel| pixel.visible.is_some())
+            .fold(0.0, |max: f64, pixel| max.max(pixel.radius));
+        let mut grid = Grid {
+            size: (2.0 * max_radius).max(1e-9),
+            cells: HashMap::new(),
+        };
+        for (i, pixel) in pixels.iter().enumerate() {
+            let vp = match &pixel.visible {
+                Some(vp) => vp,
+                None => continue,
+            };
+            let r = Vector3 {
+                x: pixel.radius,
+                y: pixel.radius,
+                z: pixel.radius,
+            };
+            let (lo, hi) =
+                (grid.cell(&(vp.rec.p - r)), grid.cell(&(vp.rec.p + r)));
+            for x in lo.0..=hi.0 {
+                for y in lo.1..=hi.1 {
+                    for z in lo.2..=hi.2 {
+                        grid.cells.entry((x, y, z)).or_default().push(i);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell(&self, p: &Point) -> (i64, i64, i64) {
+        (
+            (p.x / self.size).floor() as i64,
+            (p.y / self.size).floor() as i64,
+            (p.z / self.size).floor() as i64,
+        )
+    }
+
+    fn near(&self, p: &Point) -> &[usize] {
+        self.cells
+            .get(&self.cell(p))
+            .map_or(&[], |cell| cell.as_slice())
+    }
+}
+
+/// Whether the material at `rec` has anything but specular lobes, judged
+/// by whether it would ever scatter along the normal on either side.
+fn has_diffuse(ray: &Ray, rec: &HitRecord) -> bool {
+    rec.material.pdf(ray, rec, &rec.normal) > 0.0
+        || rec.material.pdf(ray, rec, &-rec.normal) > 0.0
+}
+
+impl Integrator for Sppm {
+    fn render(&self, scene: &Scene, camera: &dyn Camera, film: &mut Film) {
+        let mut rng = rng();
+        let (nx, ny) = (film.width, film.height);
+        let mut pixels: Vec<Pixel> = (0..nx * ny)
+            .map(|_| Pixel {
+                direct: black(),
+                radius: self.initial_radius,
+                n: 0.0,
+                tau: black(),
+                visible: None,
+                phi: black(),
+                m: 0,
+            })
+            .collect();
+        let world = scene.world.bounding_box();
+
+        for _ in 0..self.iterations {
+            // Every ray of a pass shares its wavelengths, so photons and
+            // visible points agree on what their channels mean.
+            let wavelengths = if self.spectral {
+                Some(Wavelengths::sample(rng.gen()))
+            } else {
+                None
+            };
+            let to_rgb = |color: Color| match wavelengths {
+                Some(w) => xyz_to_rgb(&w.to_xyz(&color)),
+                None => color,
+            };
+
+            for y in 0..ny {
+                for x in 0..nx {
+                    let u = (f64::from(x) + rng.gen::<f64>()) / f64::from(nx);
+                    let v = (f64::from(y) + rng.gen::<f64>()) / f64::from(ny);
+                    let CameraRay { ray, weight } =
+                        camera.generate_ray(u, v, wavelengths);
+
+                    let pixel = &mut pixels[(y * nx + x) as usize];
+                    let (direct, visible) =
+                        self.visible_point(scene, ray, weight);
+                    pixel.direct = pixel.direct + to_rgb(direct);
+                    pixel.visible = visible;
+                }
+            }
+
+            let grid = Grid::new(&pixels);
+            if let Some(world) = world {
+                for _ in 0..self.photons {
+                    self.trace_photon(
+                        scene,
+                        &world,
+                        &grid,
+                        &mut pixels,
+                        wavelengths,
+                    );
+                }
+            }
+
+            for pixel in pixels.iter_mut() {
+                let phi = to_rgb(pixel.phi);
+                pixel.end_pass(phi);
+            }
+        }
+
+        let passes = f64::from(self.iterations.max(1));
+        let photons = passes * self.photons as f64;
+        for y in 0..ny {
+            for x in 0..nx {
+                let pixel = &pixels[(y * nx + x) as usize];
+                let area = std::f64::consts::PI * pixel.radius * pixel.radius;
+                let indirect = if photons > 0.0 {
+                    pixel.tau / (photons * area)
+                } else {
+                    black()
+                };
+                film.add_sample(x, y, pixel.direct / passes + indirect);
+            }
+        }
+    }
+}
+
+impl Sppm {
+    /// Follows a camera ray through specular bounces to the first surface
+    /// that can gather photons, returning the light found directly along
+    /// the way and the point it stopped at.
+    fn visible_point<'a>(
+        &self,
+        scene: &Scene<'a>,
+        ray: Ray,
+        weight: Vector3,
+    ) -> (Color, Option<VisiblePoint<'a>>) {
+        let mut radiance = black();
+        let mut beta = weight;
+        let mut ray = ray;
+        for _ in 0..self.max_depth {
+            let world = scene.world;
+            let rec = match world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    let sky = escaped(&ray, scene, None);
+                    return (radiance + weighted(sky, &beta), None);
+                }
+            };
+            let glow = ray.upsample_color(rec.material.emitted(&ray, &rec));
+            radiance = radiance + weighted(glow, &beta);
+
+            if has_diffuse(&ray, &rec) {
+                let direct = sample_light(&ray, &rec, scene)
+                    + self.sample_bsdf(scene, &ray, &rec);
+                radiance = radiance + weighted(direct, &beta);
+                let vp = VisiblePoint { rec, ray, beta };
+                return (radiance, Some(vp));
+            }
+            let s = match rec.material.scatter(&ray, &rec) {
+                Some(s) => s,
+                None => break,
+            };
+            beta = beta.hadamard(&s.attenuation);
+            ray = s.scattered;
+        }
+        (radiance, None)
+    }
+
+    /// The light found by one bounce off `rec`, weighted against
+    /// `sample_light` as the path tracer weights it.
+    fn sample_bsdf(&self, scene: &Scene, ray: &Ray, rec: &HitRecord) -> Color {
+        let s = match rec.material.scatter(ray, rec) {
+            Some(s) => s,
+            None => return black(),
+        };
+        if s.lobe.is_specular() {
+            return black();
+        }
+        let bsdf_pdf = Some(rec.material.pdf(ray, rec, &s.scattered.direction));
+        let light = match scene.world.hit(&s.scattered, 0.001, f64::INFINITY) {
+            Some(hit) => emitted(&s.scattered, &hit, scene, bsdf_pdf),
+            None => escaped(&s.scattered, scene, bsdf_pdf),
+        };
+        weighted(light, &s.attenuation)
+    }
+
+    /// Traces a photon into `world`, the bounds of the scene, from a light
+    /// picked by the scene's light sampler as seen from its centre, adding
+    /// its flux to the visible points near each place it lands after the
+    /// first.
+    fn trace_photon(
+        &self,
+        scene: &Scene,
+        world: &Bounds,
+        grid: &Grid,
+        pixels: &mut [Pixel],
+        wavelengths: Option<Wavelengths>,
+    ) {
+        let mut rng = rng();
+        let sampler = scene.light_sampler();
+        let (index, pick) = match sampler.sample(&world.center(), rng.gen()) {
+            Some(picked) => picked,
+            None => return,
+        };
+        let light = &scene.lights[index];
+        let emission = match light.bounds() {
+            Some(_) => light.sample_le(),
+            None => sample_infinite_le(light.as_ref(), world),
+        };
+        let mut emission = match emission {
+            Some(e) if e.pdf_pos > 0.0 && e.pdf_dir > 0.0 => e,
+            _ => return,
+        };
+        emission.ray.wavelengths = wavelengths;
+
+        let cosine = if light.shape().is_some() {
+            emission.normal.dot(&emission.ray.direction).abs()
+        } else {
+            1.0
+        };
+        let radiance =
+            to_vector(emission.ray.upsample_color(emission.radiance));
+        let mut beta =
+            radiance * (cosine / (pick * emission.pdf_pos * emission.pdf_dir));
+        let mut ray = emission.ray;
+
+        for depth in 0..self.max_depth {
+            let rec = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => break,
+            };
+            if depth > 0 {
+                self.gather(grid, pixels, &ray, &rec.p, &beta);
+            }
+
+            let s = match rec.material.scatter(&ray, &rec) {
+                Some(s) => s,
+                None => break,
+            };
+            let next = beta.hadamard(&s.attenuation);
+            let brightest = |v: &Vector3| v.x.max(v.y).max(v.z);
+            let survive = (brightest(&next) / brightest(&beta)).min(1.0);
+            if survive <= 0.0 || rng.gen::<f64>() >= survive {
+                break;
+            }
+            beta = next / survive;
+            ray = s.scattered;
+        }
+    }
+
+    /// Adds the flux `beta` of a photon arriving at `p` along `ray` to the
+    /// visible points whose search radius covers it.
+    fn gather(
+        &self,
+        grid: &Grid,
+        pixels: &mut [Pixel],
+        ray: &Ray,
+        p: &Point,
+        beta: &Vector3,
+    ) {
+        let wi = -ray.direction.normalize();
+        for &i in grid.near(p) {
+            let pixel = &mut pixels[i];
+            let vp = match &pixel.visible {
+                Some(vp) => vp,
+                None => continue,
+            };
+            if (vp.rec.p - *p).norm() > pixel.radius * pixel.radius {
+                continue;
+            }
+            // `eval` includes the cosine at the visible point, which the
+            // photon density already accounts for.
+            let cosine = wi.dot(&vp.rec.normal).abs();
+            if cosine <= 0.0 {
+                continue;
+            }
+            let f = vp.rec.material.eval(&vp.ray, &vp.rec, &wi) / cosine;
+            let flux = beta.hadamard(&f).hadamard(&vp.beta);
+            pixel.phi = pixel.phi
+                + Color {
+                    r: flux.x,
+                    g: flux.y,
+                    b: flux.z,
+                };
+            pixel.m += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::scene::{Hit, Sphere};
+    use crate::util::white;
+
+    fn pixel(radius: f64) -> Pixel<'static> {
+        Pixel {
+            direct: black(),
+            radius,
+            n: 0.0,
+            tau: black(),
+            visible: None,
+            phi: black(),
+            m: 0,
+        }
+    }
+
+    /// A visible point where a ray from `origin` towards the centre meets
+    /// `sphere`.
+    fn visible<'a>(sphere: &'a Sphere, origin: Point) -> VisiblePoint<'a> {
+        let ray = Ray {
+            origin,
+            direction: Point::origin() - origin,
+            wavelengths: None,
+        };
+        VisiblePoint {
+            rec: sphere.hit(&ray, 0.001, f64::INFINITY).unwrap(),
+            ray,
+            beta: white(),
+        }
+    }
+
+    #[test]
+    fn test_end_pass_keeps_alpha_of_the_photons() {
+        let mut p = pixel(1.0);
+        p.m = 3;
+        p.end_pass(Color {
+            r: 3.0,
+            g: 3.0,
+            b: 3.0,
+        });
+
+        assert!((p.n - 2.0).abs() < 1e-12);
+        assert!((p.radius * p.radius - 2.0 / 3.0).abs() < 1e-12);
+        // The flux shrinks with the disc, so its density is unchanged.
+        assert!((p.tau.r - 2.0).abs() < 1e-12);
+        assert_eq!(p.m, 0);
+        assert_eq!(p.phi, black());
+
+        // A pass without photons leaves the estimate alone.
+        let (radius, tau) = (p.radius, p.tau);
+        p.end_pass(black());
+        assert_eq!(p.radius, radius);
+        assert_eq!(p.tau, tau);
+    }
+
+    #[test]
+    fn test_grid_finds_visible_points_near_a_photon() {
+        let sphere = Sphere::new(
+            Point::origin(),
+            1.0,
+            Box::new(Lambertian {
+                albedo: Vector3::zero(),
+            }),
+        );
+        let mut pixels = vec![pixel(0.1), pixel(0.1), pixel(0.1)];
+        pixels[0].visible = Some(visible(
+            &sphere,
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: 3.0,
+            },
+        ));
+        pixels[1].visible = Some(visible(
+            &sphere,
+            Point {
+                x: 3.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        ));
+        let grid = Grid::new(&pixels);
+        let near_front = Point {
+            x: 0.05,
+            y: -0.05,
+            z: 1.0,
+        };
+
+        assert!(grid.near(&near_front).contains(&0));
+        assert!(!grid.near(&near_front).contains(&1));
+        assert!(grid.near(&Point::origin()).is_empty());
+        for p in &[near_front, Point::origin()] {
+            assert!(!grid.near(p).contains(&2));
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use crate::point::Point;
+use crate::vector::Vector3;
+
+pub trait Texture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Vector3;
+}
+
+pub struct ConstantTexture {
+    pub color: Vector3,
+}
+
+impl Texture for ConstantTexture {
+    fn value(&self, _u: f64, _v: f64, _p: &Point) -> Vector3 {
+        self.color
+    }
+}
+
+/// An image sampled at the hit's surface coordinates. Texel values are used
+/// as stored, without any gamma decoding.
+pub struct ImageTexture {
+    image: image::Rgb32FImage,
+}
+
+impl ImageTexture {
+    pub fn new(image: image::Rgb32FImage) -> Self {
+        ImageTexture { image }
+    }
+
+    pub fn open<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> image::ImageResult<Self> {
+        Ok(ImageTexture::new(image::open(path)?.into_rgb32f()))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point) -> Vector3 {
+        let (width, height) = self.image.dimensions();
+        let u = u.clamp(0.0, 1.0);
+        let v = 1.0 - v.clamp(0.0, 1.0);
+        let x = ((u * f64::from(width)) as u32).min(width - 1);
+        let y = ((v * f64::from(height)) as u32).min(height - 1);
+        let texel = self.image.get_pixel(x, y);
+        Vector3 {
+            x: f64::from(texel[0]),
+            y: f64::from(texel[1]),
+            z: f64::from(texel[2]),
+        }
+    }
+}
@@ -1,52 +1,283 @@
 use rand::prelude::*;
 
+use crate::aov::PathAovs;
 use crate::color::Color;
+use crate::material::Lobe;
 use crate::point::Point;
 use crate::ray::Ray;
-use crate::scene::Hit;
+use crate::sampler::rng;
+use crate::scene::{HitRecord, Scene};
 use crate::vector::Vector3;
 
-pub fn render_ray<T: Hit + ?Sized>(
+/// Limits on how far `render_ray` follows a path. Each kind of bounce has
+/// its own budget, so glass can be followed much deeper than diffuse
+/// interreflection. After `roulette_depth` bounces paths are ended at
+/// random, with a chance that falls with their throughput, and survivors
+/// are weighted up to keep the estimate unbiased.
+#[derive(Copy, Clone, Debug)]
+pub struct PathSettings {
+    pub max_diffuse: usize,
+    pub max_specular: usize,
+    pub max_transmission: usize,
+    pub roulette_depth: usize,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings {
+            max_diffuse: 16,
+            max_specular: 64,
+            max_transmission: 64,
+            roulette_depth: 3,
+        }
+    }
+}
+
+impl PathSettings {
+    pub fn max_bounces(&self, lobe: Lobe) -> usize {
+        match lobe {
+            Lobe::Diffuse => self.max_diffuse,
+            Lobe::Specular => self.max_specular,
+            Lobe::Transmission => self.max_transmission,
+        }
+    }
+}
+
+pub(crate) fn gray(value: f64) -> Color {
+    Color {
+        r: value,
+        g: value,
+        b: value,
+    }
+}
+
+pub(crate) fn black() -> Color {
+    gray(0.0)
+}
+
+pub(crate) fn white() -> Vector3 {
+    Vector3 {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    }
+}
+
+pub(crate) fn to_vector(color: Color) -> Vector3 {
+    Vector3 {
+        x: color.r,
+        y: color.g,
+        z: color.b,
+    }
+}
+
+pub(crate) fn weighted(color: Color, weight: &Vector3) -> Color {
+    Color {
+        r: color.r * weight.x,
+        g: color.g * weight.y,
+        b: color.b * weight.z,
+    }
+}
+
+/// Follows `ray` through the scene within the limits of `settings`,
+/// gathering the light reaching it at each bounce.
+pub fn render_ray(ray: &Ray, scene: &Scene, settings: &PathSettings) -> Color {
+    trace_path(ray, scene, settings, None)
+}
+
+/// Like `render_ray`, also returning the path's output variables.
+pub fn render_ray_aovs(
+    ray: &Ray,
+    scene: &Scene,
+    settings: &PathSettings,
+) -> (Color, PathAovs) {
+    let mut aovs = PathAovs::default();
+    let radiance = trace_path(ray, scene, settings, Some(&mut aovs));
+    aovs.finish();
+    (radiance, aovs)
+}
+
+/// The path tracing loop behind `render_ray`, recording output variables
+/// into `aovs` if given. Along the way `bsdf_pdf` holds the density with
+/// which the last bounce picked the ray's direction, or `None` if it came
+/// from the camera or a specular bounce, and is used to weight light found
+/// by the ray against light found by `sample_light`.
+fn trace_path(
     ray: &Ray,
-    world: &T,
-    depth: usize,
+    scene: &Scene,
+    settings: &PathSettings,
+    mut aovs: Option<&mut PathAovs>,
 ) -> Color {
-    if let Some(rec) = world.hit(ray, 0.001, std::f64::INFINITY) {
-        match (depth < 50, rec.material.scatter(ray, &rec)) {
-            (true, Some(s)) => {
-                let col = render_ray(&s.scattered, world, depth + 1);
-                Color {
-                    r: col.r * s.attenuation.x,
-                    g: col.g * s.attenuation.y,
-                    b: col.b * s.attenuation.z,
+    let mut rng = rng();
+    let mut radiance = black();
+    let mut throughput = Vector3 {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    };
+    let mut ray = *ray;
+    let mut bsdf_pdf = None;
+    let mut bounces = [0; 3];
+
+    for depth in 0.. {
+        let rec = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => {
+                let light =
+                    weighted(escaped(&ray, scene, bsdf_pdf), &throughput);
+                radiance = radiance + light;
+                if let Some(aovs) = aovs.as_deref_mut() {
+                    aovs.add_light(depth, light);
                 }
+                break;
             }
-            (_, _) => Color {
-                r: 0.0,
-                g: 0.0,
-                b: 0.0,
-            },
+        };
+        let hit = weighted(emitted(&ray, &rec, scene, bsdf_pdf), &throughput);
+        let direct = weighted(sample_light(&ray, &rec, scene), &throughput);
+        radiance = radiance + hit + direct;
+        if let Some(aovs) = aovs.as_deref_mut() {
+            if depth == 0 {
+                aovs.first_hit(&ray, &rec);
+            }
+            aovs.add_light(depth, hit);
+            aovs.add_light(depth + 1, direct);
         }
-    } else {
-        let unit_direction = ray.direction.normalize();
-        let t = 0.5 * unit_direction.y + 1.0;
-        let white = Color {
-            r: 1.0,
-            g: 1.0,
-            b: 1.0,
+
+        let s = match rec.material.scatter(&ray, &rec) {
+            Some(s) => s,
+            None => break,
+        };
+        if let Some(aovs) = aovs.as_deref_mut() {
+            aovs.scattered(s.lobe, &s.attenuation);
+        }
+        let count = match s.lobe {
+            Lobe::Diffuse => &mut bounces[0],
+            Lobe::Specular => &mut bounces[1],
+            Lobe::Transmission => &mut bounces[2],
+        };
+        *count += 1;
+        if *count > settings.max_bounces(s.lobe) {
+            break;
+        }
+
+        bsdf_pdf = if s.lobe.is_specular() {
+            None
+        } else {
+            Some(rec.material.pdf(&ray, &rec, &s.scattered.direction))
+        };
+        throughput = throughput.hadamard(&s.attenuation);
+        if depth + 1 >= settings.roulette_depth {
+            let survive =
+                throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+            if survive <= 0.0 || rng.gen::<f64>() >= survive {
+                break;
+            }
+            throughput = throughput / survive;
+        }
+        ray = s.scattered;
+    }
+    radiance
+}
+
+/// The light a ray picks up from an emissive surface it hits, weighted
+/// against the chance of `sample_light` having found the same point.
+pub(crate) fn emitted(
+    ray: &Ray,
+    rec: &HitRecord,
+    scene: &Scene,
+    bsdf_pdf: Option<f64>,
+) -> Color {
+    let emitted = rec.material.emitted(ray, rec);
+    if emitted == black() {
+        return black();
+    }
+    let sampler = scene.light_sampler();
+    let weight = match (bsdf_pdf, sampler.light_for(rec.object)) {
+        (Some(pdf), Some(index)) => {
+            let select_pdf = sampler.pmf(&ray.origin, index);
+            let light = &scene.lights[index];
+            power_heuristic(
+                pdf,
+                select_pdf * light.pdf(&ray.origin, &ray.direction),
+            )
+        }
+        _ => 1.0,
+    };
+    weight * ray.upsample_color(emitted)
+}
+
+/// The light carried by a ray that leaves the scene, weighted against the
+/// chance of `sample_light` having found the same light.
+pub(crate) fn escaped(
+    ray: &Ray,
+    scene: &Scene,
+    bsdf_pdf: Option<f64>,
+) -> Color {
+    let sampler = scene.light_sampler();
+    sampler.infinite_lights().iter().fold(black(), |sum, &i| {
+        let light = &scene.lights[i];
+        let weight = match bsdf_pdf {
+            Some(pdf) => power_heuristic(
+                pdf,
+                sampler.pmf(&ray.origin, i)
+                    * light.pdf(&ray.origin, &ray.direction),
+            ),
+            None => 1.0,
         };
-        let light_blue = Color {
-            r: 0.5,
-            g: 0.7,
-            b: 1.0,
+        sum + weight * ray.upsample_color(light.le(ray))
+    })
+}
+
+/// The power heuristic weight, with an exponent of two, for a sample drawn
+/// with density `f` when it could also have been drawn with density `g`.
+pub fn power_heuristic(f: f64, g: f64) -> f64 {
+    if f.is_infinite() {
+        return 1.0;
+    }
+    let (f2, g2) = (f * f, g * g);
+    if f2 + g2 == 0.0 { 0.0 } else { f2 / (f2 + g2) }
+}
+
+/// Estimates the light reaching `rec` directly from one of the scene's
+/// lights, picked by its sampler, and reflected back along `ray`.
+pub fn sample_light(ray: &Ray, rec: &HitRecord, scene: &Scene) -> Color {
+    let (index, select_pdf) =
+        match scene.light_sampler().sample(&rec.p, rng().gen()) {
+            Some(picked) => picked,
+            None => return black(),
         };
+    let light = &scene.lights[index];
+    let sample = match light.sample(&rec.p) {
+        Some(sample) if sample.pdf > 0.0 => sample,
+        _ => return black(),
+    };
+    let f = rec.material.eval(ray, rec, &sample.direction);
+    if f == Vector3::zero()
+        || !scene.unoccluded(rec.p, sample.direction, sample.distance)
+    {
+        return black();
+    }
 
-        (1.0 - t) * white + t * light_blue
+    let light_pdf = sample.pdf * select_pdf;
+    let weight = if light.is_delta() {
+        1.0
+    } else {
+        power_heuristic(
+            light_pdf,
+            rec.material.pdf(ray, rec, &sample.direction),
+        )
+    };
+    let radiance = ray.upsample_color(sample.radiance);
+    let scale = weight / light_pdf;
+    Color {
+        r: radiance.r * f.x * scale,
+        g: radiance.g * f.y * scale,
+        b: radiance.b * f.z * scale,
     }
 }
 
 pub fn random_in_unit_sphere() -> Point {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     loop {
         let p =
             2.0 * Vector3 {
@@ -67,3 +298,146 @@ pub fn random_in_unit_sphere() -> Point {
         };
     }
 }
+
+/// A direction in the local frame around +z, distributed proportionally to
+/// the cosine of its angle with the z axis.
+pub fn random_cosine_direction() -> Vector3 {
+    let mut rng = rng();
+    let r1: f64 = rng.gen();
+    let r2: f64 = rng.gen();
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    Vector3 {
+        x: phi.cos() * r2.sqrt(),
+        y: phi.sin() * r2.sqrt(),
+        z: (1.0 - r2).sqrt(),
+    }
+}
+
+/// A direction picked uniformly over the unit sphere.
+pub fn random_unit_vector() -> Vector3 {
+    let mut rng = rng();
+    let z = 1.0 - 2.0 * rng.gen::<f64>();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+    Vector3 {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::{GradientSky, Light, LightSample};
+    use crate::material::Metal;
+    use crate::scene::{Bounds, HitList, Sphere};
+
+    #[test]
+    fn test_cosine_directions_favour_the_pole() {
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let d = random_cosine_direction();
+
+            assert!(d.z >= 0.0);
+            assert!((d.length() - 1.0).abs() < 1e-9);
+            sum += d.z;
+        }
+        let mean = sum / f64::from(n);
+        assert!((mean - 2.0 / 3.0).abs() < 0.01, "{}", mean);
+    }
+
+    /// A light with bounds that fails the test if an escaped ray asks it
+    /// anything, as an area light would have to intersect its shape.
+    struct Bounded;
+
+    impl Light for Bounded {
+        fn sample(&self, _p: &Point) -> Option<LightSample> {
+            None
+        }
+
+        fn pdf(&self, _p: &Point, _direction: &Vector3) -> f64 {
+            panic!("escaped ray queried a bounded light");
+        }
+
+        fn le(&self, _ray: &Ray) -> Color {
+            panic!("escaped ray queried a bounded light");
+        }
+
+        fn bounds(&self) -> Option<Bounds> {
+            Some(Bounds {
+                min: Point::origin(),
+                max: Point::origin(),
+            })
+        }
+
+        fn power(&self) -> f64 {
+            1.0
+        }
+    }
+
+    #[test]
+    fn test_escaped_rays_only_query_infinite_lights() {
+        let world = HitList::new();
+        let mut scene = Scene::new(&world);
+        scene.lights.push(Box::new(Bounded));
+        scene.lights.push(Box::new(GradientSky));
+        let ray = Ray {
+            origin: Point::origin(),
+            direction: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            wavelengths: None,
+        };
+
+        let light = escaped(&ray, &scene, Some(1.0));
+        assert!(light.b > 0.0);
+        assert_eq!(scene.light_sampler().infinite_lights(), &[1]);
+    }
+
+    #[test]
+    fn test_max_specular_stops_at_the_first_mirror_bounce() {
+        let mut world = HitList::new();
+        world.push(Sphere::new(
+            Point::origin(),
+            1.0,
+            Box::new(Metal {
+                albedo: Vector3 {
+                    x: 1.0,
+                    y: 1.0,
+                    z: 1.0,
+                },
+                fuzz: 0.0,
+                film: None,
+            }),
+        ));
+        let mut scene = Scene::new(&world);
+        scene.lights.push(Box::new(GradientSky));
+        let ray = Ray {
+            origin: Point {
+                x: 0.0,
+                y: 0.0,
+                z: 5.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            wavelengths: None,
+        };
+        let settings = |max_specular| PathSettings {
+            max_specular,
+            roulette_depth: usize::MAX,
+            ..PathSettings::default()
+        };
+
+        let stopped = render_ray(&ray, &scene, &settings(0));
+        let reflected = render_ray(&ray, &scene, &settings(1));
+        assert_eq!(stopped.r + stopped.g + stopped.b, 0.0);
+        assert!(reflected.b > 0.0);
+    }
+}
@@ -26,6 +26,15 @@ impl Vector3 {
         }
     }
 
+    #[inline]
+    pub fn hadamard(&self, other: &Vector3) -> Vector3 {
+        Vector3 {
+            x: self.x * other.x,
+            y: self.y * other.y,
+            z: self.z * other.z,
+        }
+    }
+
     #[inline]
     pub fn dot(&self, other: &Vector3) -> f64 {
         self.x * other.x + self.y * other.y + self.z * other.z
@@ -214,6 +223,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_hadamard() {
+        let vec1 = Vector3 {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+        let vec2 = Vector3 {
+            x: 4.0,
+            y: 5.0,
+            z: 6.0,
+        };
+
+        assert_eq!(
+            vec1.hadamard(&vec2),
+            Vector3 {
+                x: 4.0,
+                y: 10.0,
+                z: 18.0,
+            }
+        );
+    }
+
     #[test]
     fn test_scalar_mul() {
         let vec = Vector3 {